@@ -11,34 +11,64 @@ enum ReturnType {
     ServerResponse,
     String,
     Json,
+    /// `BaseResponse` or any other type it converts from, e.g. `Vec<u8>`.
+    Response,
+    /// Any other type, serialized with `serde`.
+    Serialize,
     Result(Box<Self>),
 }
 
-fn extract_result_return_type(segment: &syn::PathSegment) -> Option<ReturnType> {
-    assert!(segment.ident == "Result");
-    match &segment.arguments {
-        syn::PathArguments::AngleBracketed(args) => {
-            let ok_type = args.args[0].clone();
+/// Whether `ty` is the `u8` or `str` primitive named `name`.
+fn is_primitive(ty: &syn::Type, name: &str) -> bool {
+    matches!(ty, syn::Type::Path(type_path) if type_path.path.is_ident(name))
+}
 
-            if let syn::GenericArgument::Type(syn::Type::Path(type_path)) = ok_type {
-                assert!(type_path.path.segments.len() == 1);
-                match type_path.path.segments[0].ident.to_string().as_str() {
-                    "ServerResponse" => {
-                        Some(ReturnType::Result(Box::new(ReturnType::ServerResponse)))
-                    }
-                    "String" => Some(ReturnType::Result(Box::new(ReturnType::String))),
-                    "Value" => Some(ReturnType::Result(Box::new(ReturnType::Json))),
-                    _ => None,
-                }
-            } else {
-                None
-            }
+fn classify_return_type(ty: &syn::Type) -> Result<ReturnType, &'static str> {
+    let type_path = match ty {
+        syn::Type::Path(type_path) => type_path,
+        syn::Type::Reference(reference) if is_primitive(&reference.elem, "str") => {
+            return Ok(ReturnType::Response)
         }
-        _ => None,
+        _ => return Ok(ReturnType::Serialize),
+    };
+    let Some(segment) = type_path.path.segments.last() else {
+        return Ok(ReturnType::Serialize);
+    };
+    match segment.ident.to_string().as_str() {
+        "ServerResponse" => Ok(ReturnType::ServerResponse),
+        "String" => Ok(ReturnType::String),
+        "Value" => Ok(ReturnType::Json),
+        "BaseResponse" | "Bytes" => Ok(ReturnType::Response),
+        "Vec" => match &segment.arguments {
+            syn::PathArguments::AngleBracketed(args)
+                if matches!(
+                    args.args.first(),
+                    Some(syn::GenericArgument::Type(item)) if is_primitive(item, "u8")
+                ) =>
+            {
+                Ok(ReturnType::Response)
+            }
+            _ => Ok(ReturnType::Serialize),
+        },
+        "Result" => match &segment.arguments {
+            syn::PathArguments::AngleBracketed(args) => match args.args.first() {
+                Some(syn::GenericArgument::Type(ok_type)) => {
+                    Ok(ReturnType::Result(Box::new(classify_return_type(ok_type)?)))
+                }
+                _ => Err("Unsupported [Result] return type"),
+            },
+            _ => Err("Unsupported [Result] return type"),
+        },
+        _ => Ok(ReturnType::Serialize),
     }
 }
 
 /// ## Oblivion Macro for Route Handler
+///
+/// Handlers may return `ServerResponse`, `BaseResponse` or any type it converts
+/// from, such as `String`, `Value` or `Vec<u8>`, or with the `serde` feature of
+/// `oblivion`, any other type implementing `Serialize`, which is sent as JSON.
+/// Each of them but `ServerResponse` may also be wrapped in a `Result`.
 #[proc_macro_attribute]
 pub fn async_route(_: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as ItemFn);
@@ -54,43 +84,9 @@ pub fn async_route(_: TokenStream, item: TokenStream) -> TokenStream {
         syn::ReturnType::Type(_, ty) => ty,
     };
 
-    let return_type = match &input.sig.output {
-        syn::ReturnType::Default => {
-            return TokenStream::from(
-                quote! { compile_error!("Handler function must have a return type"); },
-            )
-        }
-        syn::ReturnType::Type(_, ty) => match ty.as_ref() {
-            syn::Type::Path(type_path) => {
-                if type_path.path.segments.len() == 1 {
-                    match type_path.path.segments[0].ident.to_string().as_str() {
-                        "ServerResponse" => ReturnType::ServerResponse,
-                        "String" => ReturnType::String,
-                        "Value" => ReturnType::Json,
-                        "Result" => {
-                            let extracted_type =
-                                extract_result_return_type(&type_path.path.segments[0]);
-                            if extracted_type.is_none() {
-                                return TokenStream::from(
-                                    quote! { compile_error!("Unsupported [Result] return type")},
-                                );
-                            }
-                            extracted_type.unwrap()
-                        }
-                        _ => {
-                            return TokenStream::from(
-                                quote! { compile_error!("Unsupported path like return type"); },
-                            )
-                        }
-                    }
-                } else {
-                    return TokenStream::from(
-                        quote! { compile_error!("Unsupported complex path like return type"); },
-                    );
-                }
-            }
-            _ => return TokenStream::from(quote! { compile_error!("Unsupported return type"); }),
-        },
+    let return_type = match classify_return_type(func_return) {
+        Ok(return_type) => return_type,
+        Err(message) => return TokenStream::from(quote! { compile_error!(#message); }),
     };
     let input_block = &input.block;
     let func_block = match return_type {
@@ -99,16 +95,24 @@ pub fn async_route(_: TokenStream, item: TokenStream) -> TokenStream {
                 #input_block
             })
         },
-        ReturnType::String | ReturnType::Json => quote! {
+        ReturnType::String | ReturnType::Json | ReturnType::Response => quote! {
             Box::pin(async move {
-                let result = async move {
+                let result: #func_return = async move {
                     #input_block
                 }.await;
                 Ok(result.into())
             })
         },
+        ReturnType::Serialize => quote! {
+            Box::pin(async move {
+                let result: #func_return = async move {
+                    #input_block
+                }.await;
+                Ok(oblivion::__serialize_response!(&result)?)
+            })
+        },
         ReturnType::Result(return_type) => match *return_type {
-            ReturnType::String | ReturnType::Json => quote! {
+            ReturnType::String | ReturnType::Json | ReturnType::Response => quote! {
                 Box::pin(async move {
                     let result: #func_return = async move {
                         #input_block
@@ -116,6 +120,14 @@ pub fn async_route(_: TokenStream, item: TokenStream) -> TokenStream {
                     Ok(result?.into())
                 })
             },
+            ReturnType::Serialize => quote! {
+                Box::pin(async move {
+                    let result: #func_return = async move {
+                        #input_block
+                    }.await;
+                    Ok(oblivion::__serialize_response!(&result?)?)
+                })
+            },
             ReturnType::ServerResponse => {
                 return TokenStream::from(
                     quote! { compile_error!("Unsupported [ServerResponse] in [Result] return type")},
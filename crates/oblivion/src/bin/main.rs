@@ -1,8 +1,10 @@
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use futures::future::BoxFuture;
+use oblivion::models::acl::AccessList;
 use oblivion::models::client::Client;
+use oblivion::models::pool::Pool;
 use oblivion::models::render::BaseResponse;
-use oblivion::models::router::{RoutePath, RouteType, Router};
+use oblivion::models::router::{Route, RoutePath, RouteType, Router};
 use oblivion::models::server::Server;
 use oblivion::models::session::Session;
 use oblivion::path_route;
@@ -12,6 +14,7 @@ use serde_json::{json, Value};
 use std::env::args;
 use std::sync::Arc;
 use tokio::time::Instant;
+use tracing_subscriber::EnvFilter;
 
 #[async_route]
 fn handler(_session: Session) -> String {
@@ -21,15 +24,11 @@ fn handler(_session: Session) -> String {
 }
 
 #[async_route]
-fn welcome(session: Session) -> Result<String> {
-    let ip_address = session.request.get_ip();
-    if ip_address != "127.0.0.1" {
-        return Err(anyhow!("禁止访问"));
-    }
-    Ok(format!(
+fn welcome(session: Session) -> String {
+    format!(
         "欢迎进入信息绝对安全区, 来自[{}]的朋友",
         session.request.get_ip()
-    ))
+    )
 }
 
 #[async_route]
@@ -68,6 +67,12 @@ async fn callback_handler(mut session: Session) -> Value {
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
     let mut args: Vec<String> = args().collect();
     if args.len() <= 1 {
         args.push("serve".to_string());
@@ -76,13 +81,16 @@ async fn main() -> Result<()> {
         args.push("/welcome".to_string());
     }
     match args[1].as_str() {
-        "bench" => loop {
-            let now = Instant::now();
-            let client = Client::connect(&format!("127.0.0.1:7076{}", args[2])).await?;
-            client.recv().await?.text()?;
-            client.close().await?;
-            println!("执行时间: {}", now.elapsed().as_millis());
-        },
+        "bench" => {
+            let pool = Pool::default();
+            loop {
+                let now = Instant::now();
+                pool.fetch(&format!("127.0.0.1:7076{}", args[2]))
+                    .await?
+                    .text()?;
+                println!("执行时间: {}", now.elapsed().as_millis());
+            }
+        }
         "socket" => {
             let client = Client::connect(&format!("127.0.0.1:7076{}", args[2])).await?;
             client.recv().await?.text()?;
@@ -106,12 +114,18 @@ async fn main() -> Result<()> {
 
             router.route(RoutePath::new("/handler", RouteType::Path), handler);
 
-            path_route!(router, "/welcome" => welcome);
+            router.register(
+                RoutePath::new("/welcome", RouteType::Path),
+                Route::new(welcome).access(AccessList::new().allow("127.0.0.1".parse()?)),
+            );
             path_route!(router, "/json" => json);
             path_route!(router, "/alive" => alive);
             path_route!(router, "/callback" => callback_handler);
 
-            let server = Server::new("0.0.0.0", 7076, router);
+            let server = Server::builder(router)
+                .address("0.0.0.0:7076")
+                .persistent(true)
+                .build();
             server.run().await?;
         }
         _ => {
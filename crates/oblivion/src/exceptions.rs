@@ -14,6 +14,8 @@ pub enum Exception {
     InvalidHeader(String),
     #[error("Link requests to the server are denied, either due to insufficient privileges or an attack on the server.")]
     ConnectionRefusedError,
+    #[error("Connection rejected by the server: {reason}")]
+    ConnectionRejected { reason: String },
     #[error("Wrong Oblivion address: {entrance}")]
     InvalidOblivion { entrance: String },
     #[error("Exceeded expected packet size: {size}")]
@@ -31,6 +33,42 @@ pub enum Exception {
     DecryptError { error: Unspecified },
     #[error("Trying to read or write a closed connection.")]
     ConnectionClosed,
+    #[error("Server responded with error {code}: {message}")]
+    ServerError { code: u32, message: String },
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    #[error("Handshake did not complete within {timeout:?}.")]
+    HandshakeTimeout { timeout: std::time::Duration },
+    #[error("No data received from the peer within {timeout:?}.")]
+    ReadTimeout { timeout: std::time::Duration },
+    #[error("Proxy error: {reason}")]
+    ProxyError { reason: String },
+    #[error("Failed to resolve {host}: {reason}")]
+    DnsError { host: String, reason: String },
+    #[error("Connection was not established within {timeout:?}.")]
+    ConnectTimeout { timeout: std::time::Duration },
+    #[error("Connection reset by the peer.")]
+    ConnectionReset,
+    #[error("Failed to connect: {reason}")]
+    ConnectFailed { reason: String },
+    #[error("Stream {id} was reset.")]
+    StreamReset { id: u32 },
+    #[error("Body ended before its last chunk.")]
+    BodyTruncated,
+    #[error("Failed to deserialize {target}: {reason}")]
+    DeserializeError { target: String, reason: String },
+    #[error("Session closed by the peer with code {code}: {reason}")]
+    SessionClosed { code: u32, reason: String },
+    #[error("Peer left {missed} heartbeats in a row unanswered.")]
+    HeartbeatTimeout { missed: u32 },
+    #[error("Path {path} escapes the served directory.")]
+    PathTraversal { path: String },
+    #[error("Range {start}..{end:?} is not satisfiable for {size} bytes.")]
+    RangeNotSatisfiable {
+        start: u64,
+        end: Option<u64>,
+        size: u64,
+    },
 }
 
 #[cfg(feature = "pyo3")]
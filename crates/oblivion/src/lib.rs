@@ -27,6 +27,8 @@ pub mod utils {
     pub mod gear;
     pub mod generator;
     pub mod parser;
+    #[cfg(feature = "websocket")]
+    pub mod websocket;
 }
 
 /// # Oblivion Models
@@ -69,6 +71,28 @@ macro_rules! path_route {
     }};
 }
 
+/// Serialize the value returned by a handler marked with `async_route` as a JSON
+/// response, which takes the `serde` feature.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __serialize_response {
+    ($value:expr) => {
+        $crate::models::render::BaseResponse::serialize($value)
+    };
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __serialize_response {
+    ($value:expr) => {
+        compile_error!(
+            "handlers returning types other than ServerResponse, BaseResponse, String, Value, Vec<u8> or Bytes need the `serde` feature of oblivion"
+        )
+    };
+}
+
 /// Startswith Routing Macros
 ///
 /// Starting routes can be simply implemented using the start route macro:
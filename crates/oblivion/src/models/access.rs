@@ -0,0 +1,350 @@
+//! # Oblivion Access Log
+//!
+//! Writes one line per handled connection to a file, rotating it once it grows too large.
+//! Lines are handed to a dedicated writer thread so that file I/O never blocks a request.
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::json;
+use tracing::error;
+
+use crate::exceptions::Exception;
+
+/// Access Log Format
+///
+/// - `common`: a Common Log Format like line followed by the session duration in milliseconds.
+/// - `json`: one JSON object per line.
+/// - anything else: a template where `{ip}`, `{port}`, `{identity}`, `{time}`, `{request}`,
+///   `{entrance}`, `{status}`, `{bytes_in}`, `{bytes_out}`, `{duration_ms}` and
+///   `{duration_us}` are replaced by the fields of the entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogFormat {
+    Common,
+    Json,
+    Template(String),
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = Exception;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        Ok(match format {
+            "common" => Self::Common,
+            "json" => Self::Json,
+            template => Self::Template(template.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for AccessLogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Common => write!(f, "common"),
+            Self::Json => write!(f, "json"),
+            Self::Template(template) => write!(f, "{}", template),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for AccessLogFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for AccessLogFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Access Log Configuration
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AccessLogConfig {
+    /// File the access log is appended to.
+    pub path: PathBuf,
+    #[cfg_attr(feature = "serde", serde(default = "default_format"))]
+    pub format: AccessLogFormat,
+    /// Size in bytes after which the file is rotated, `None` never rotates.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_size: Option<u64>,
+    /// Number of rotated files kept as `path.1` to `path.N`.
+    #[cfg_attr(feature = "serde", serde(default = "default_max_files"))]
+    pub max_files: usize,
+}
+
+#[cfg(feature = "serde")]
+fn default_format() -> AccessLogFormat {
+    AccessLogFormat::Common
+}
+
+#[cfg(feature = "serde")]
+fn default_max_files() -> usize {
+    5
+}
+
+impl AccessLogConfig {
+    pub fn new(path: impl Into<PathBuf>, format: AccessLogFormat) -> Self {
+        Self {
+            path: path.into(),
+            format,
+            max_size: None,
+            max_files: 5,
+        }
+    }
+
+    /// Rotate the file once it exceeds `max_size` bytes, keeping `max_files` old files.
+    pub fn rotate(mut self, max_size: u64, max_files: usize) -> Self {
+        self.max_size = Some(max_size);
+        self.max_files = max_files;
+        self
+    }
+}
+
+/// Access Log Entry
+///
+/// Fields describing a handled connection, `request` and `entrance` are `-` when
+/// the handshake did not complete.
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub ip: String,
+    pub port: u16,
+    pub identity: Option<String>,
+    pub time: DateTime<Local>,
+    pub request: String,
+    pub entrance: String,
+    pub status: u32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub duration: Duration,
+}
+
+impl AccessLogEntry {
+    /// Render the entry in `format`, without the trailing newline.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use chrono::Local;
+    /// use oblivion::models::access::{AccessLogEntry, AccessLogFormat};
+    ///
+    /// let entry = AccessLogEntry {
+    ///     ip: "127.0.0.1".to_string(),
+    ///     port: 51234,
+    ///     identity: Some("alice".to_string()),
+    ///     time: Local::now(),
+    ///     request: "CONNECT /welcome Oblivion/2.0".to_string(),
+    ///     entrance: "/welcome".to_string(),
+    ///     status: 200,
+    ///     bytes_in: 69,
+    ///     bytes_out: 165,
+    ///     duration: Duration::from_millis(6),
+    /// };
+    ///
+    /// let format = AccessLogFormat::Template("{identity}@{ip} {entrance} {status} {duration_ms}ms".into());
+    /// assert_eq!(entry.format(&format), "alice@127.0.0.1 /welcome 200 6ms");
+    ///
+    /// // Fields supplied by the peer are neither expanded nor able to break the line.
+    /// let forged = AccessLogEntry {
+    ///     request: "CONNECT /{status}\" 200\n Oblivion/2.0".to_string(),
+    ///     entrance: "/{status}".to_string(),
+    ///     ..entry
+    /// };
+    /// assert_eq!(forged.format(&format), "alice@127.0.0.1 /{status} 200 6ms");
+    /// assert!(forged
+    ///     .format(&AccessLogFormat::Common)
+    ///     .contains(r#""CONNECT /{status}\" 200\x0a Oblivion/2.0" 200"#));
+    /// ```
+    pub fn format(&self, format: &AccessLogFormat) -> String {
+        let identity = escape(self.identity.as_deref().unwrap_or("-"));
+        match format {
+            AccessLogFormat::Common => format!(
+                "{} - {} [{}] \"{}\" {} {} {}",
+                self.ip,
+                identity,
+                self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+                escape(&self.request),
+                self.status,
+                self.bytes_out,
+                self.duration.as_millis()
+            ),
+            AccessLogFormat::Json => json!({
+                "ip": self.ip,
+                "port": self.port,
+                "identity": self.identity,
+                "time": self.time.to_rfc3339(),
+                "request": self.request,
+                "entrance": self.entrance,
+                "status": self.status,
+                "bytes_in": self.bytes_in,
+                "bytes_out": self.bytes_out,
+                "duration_us": self.duration.as_micros() as u64,
+            })
+            .to_string(),
+            AccessLogFormat::Template(template) => {
+                let mut line = String::with_capacity(template.len());
+                let mut rest = template.as_str();
+                while let Some(start) = rest.find('{') {
+                    line.push_str(&rest[..start]);
+                    rest = &rest[start..];
+                    let field = rest
+                        .find('}')
+                        .and_then(|end| Some((self.field(&rest[1..end])?, end)));
+                    match field {
+                        Some((value, end)) => {
+                            line.push_str(&value);
+                            rest = &rest[end + 1..];
+                        }
+                        None => {
+                            line.push('{');
+                            rest = &rest[1..];
+                        }
+                    }
+                }
+                line.push_str(rest);
+                line
+            }
+        }
+    }
+
+    /// Value of the template field `name`, peer supplied text escaped.
+    fn field(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "ip" => self.ip.clone(),
+            "port" => self.port.to_string(),
+            "identity" => escape(self.identity.as_deref().unwrap_or("-")),
+            "time" => self.time.to_rfc3339(),
+            "request" => escape(&self.request),
+            "entrance" => escape(&self.entrance),
+            "status" => self.status.to_string(),
+            "bytes_in" => self.bytes_in.to_string(),
+            "bytes_out" => self.bytes_out.to_string(),
+            "duration_ms" => self.duration.as_millis().to_string(),
+            "duration_us" => self.duration.as_micros().to_string(),
+            _ => return None,
+        })
+    }
+}
+
+/// Escape quotes, backslashes and control characters so that peer supplied text
+/// cannot end a quoted field or start a new line.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            char if char.is_control() => escaped.push_str(&format!("\\x{:02x}", char as u32)),
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+/// Most lines waiting for the writer thread, further lines are dropped.
+const MAX_PENDING_LINES: usize = 4096;
+
+/// Oblivion Access Logger
+///
+/// Formats entries on the caller and appends them from a writer thread, which
+/// stops once the logger is dropped and every pending line is written.
+pub struct AccessLogger {
+    format: AccessLogFormat,
+    lines: SyncSender<String>,
+}
+
+impl AccessLogger {
+    pub fn open(config: AccessLogConfig) -> Result<Self> {
+        let file = open(&config.path)?;
+        let size = file.metadata()?.len();
+        let format = config.format.clone();
+        let (lines, receiver) = mpsc::sync_channel(MAX_PENDING_LINES);
+        let writer = LogWriter { config, file, size };
+        thread::Builder::new()
+            .name("oblivion-access-log".to_string())
+            .spawn(move || writer.run(receiver))?;
+        Ok(Self { format, lines })
+    }
+
+    /// Queue `entry` to be appended to the access log without waiting for the file.
+    ///
+    /// Fails if the writer thread is gone or too far behind.
+    pub fn log(&self, entry: &AccessLogEntry) -> Result<()> {
+        match self.lines.try_send(entry.format(&self.format)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(anyhow!("access log writer is too far behind")),
+            Err(TrySendError::Disconnected(_)) => Err(anyhow!("access log writer has stopped")),
+        }
+    }
+}
+
+struct LogWriter {
+    config: AccessLogConfig,
+    file: File,
+    size: u64,
+}
+
+impl LogWriter {
+    fn run(mut self, lines: Receiver<String>) {
+        for line in lines {
+            if let Err(error) = self.write(line) {
+                error!(%error, "failed to write the access log");
+            }
+        }
+    }
+
+    /// Append `line` to the file, rotating the file first if it is full.
+    fn write(&mut self, mut line: String) -> Result<()> {
+        line.push('\n');
+        if let Some(max_size) = self.config.max_size {
+            if self.size > 0 && self.size + line.len() as u64 > max_size {
+                self.rotate()?;
+                self.file = open(&self.config.path)?;
+                self.size = 0;
+            }
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&self) -> Result<()> {
+        let path = &self.config.path;
+        if self.config.max_files == 0 {
+            fs::remove_file(path)?;
+            return Ok(());
+        }
+        for index in (1..self.config.max_files).rev() {
+            let from = rotated(path, index);
+            if from.exists() {
+                fs::rename(from, rotated(path, index + 1))?;
+            }
+        }
+        fs::rename(path, rotated(path, 1))?;
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
@@ -0,0 +1,198 @@
+//! # Oblivion Access Control
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::exceptions::Exception;
+
+/// CIDR Range
+///
+/// An IPv4 or IPv6 network given as `address/prefix`, a bare address matches itself only.
+/// IPv4-mapped IPv6 peers such as `::ffff:10.0.0.1` are matched as their IPv4 address.
+///
+/// ```rust
+/// use oblivion::models::acl::Cidr;
+///
+/// let lan: Cidr = "192.168.0.0/16".parse().unwrap();
+/// let loopback: Cidr = "::1".parse().unwrap();
+///
+/// assert!(lan.contains(&"192.168.31.7".parse().unwrap()));
+/// assert!(lan.contains(&"::ffff:192.168.1.1".parse().unwrap()));
+/// assert!(!lan.contains(&"10.0.0.1".parse().unwrap()));
+/// assert!(loopback.contains(&"::1".parse().unwrap()));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn new(address: IpAddr, prefix: u8) -> Result<Self, Exception> {
+        let (address, prefix) = match address {
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) if prefix >= 96 => (IpAddr::V4(v4), prefix - 96),
+                _ => (address, prefix),
+            },
+            IpAddr::V4(_) => (address, prefix),
+        };
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix > bits {
+            return Err(Exception::InvalidConfig(format!(
+                "CIDR prefix /{} is too long for {}",
+                prefix, address
+            )));
+        }
+        let network = match address {
+            IpAddr::V4(address) => IpAddr::V4((u32::from(address) & mask_v4(prefix)).into()),
+            IpAddr::V6(address) => IpAddr::V6((u128::from(address) & mask_v6(prefix)).into()),
+        };
+        Ok(Self { network, prefix })
+    }
+
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        match (self.network, canonical(*address)) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                u32::from(address) & mask_v4(self.prefix) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                u128::from(address) & mask_v6(self.prefix) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        IpAddr::V4(_) => address,
+    }
+}
+
+fn mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+impl FromStr for Cidr {
+    type Err = Exception;
+
+    fn from_str(cidr: &str) -> Result<Self, Self::Err> {
+        let invalid = || Exception::InvalidConfig(format!("invalid CIDR range: {}", cidr));
+        let (address, prefix) = match cidr.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (cidr.trim(), None),
+        };
+        let address = address.parse::<IpAddr>().map_err(|_| invalid())?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None if address.is_ipv4() => 32,
+            None => 128,
+        };
+        Self::new(address, prefix)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Cidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// IP Access List
+///
+/// A peer is denied if it matches any `deny` range, otherwise it is permitted
+/// when `allow` is empty or the peer matches one of its ranges. Peers without an
+/// IP address, such as Unix domain socket peers, are denied by any non-empty list
+/// unless `allow_local` is set.
+///
+/// ```rust
+/// use oblivion::models::acl::AccessList;
+///
+/// let access = AccessList::new()
+///     .allow("10.0.0.0/8".parse().unwrap())
+///     .deny("10.0.13.0/24".parse().unwrap());
+///
+/// assert!(access.permits(&"10.1.2.3".parse().unwrap()));
+/// assert!(!access.permits(&"10.0.13.37".parse().unwrap()));
+/// assert!(!access.permits(&"172.16.0.1".parse().unwrap()));
+/// assert!(!access.permits_peer(None));
+/// assert!(access.allow_local(true).permits_peer(None));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct AccessList {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+    /// Permit peers without an IP address despite the ranges.
+    pub allow_local: bool,
+}
+
+impl AccessList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, cidr: Cidr) -> Self {
+        self.allow.push(cidr);
+        self
+    }
+
+    pub fn deny(mut self, cidr: Cidr) -> Self {
+        self.deny.push(cidr);
+        self
+    }
+
+    /// Permit peers without an IP address, which are denied by default once any range is set.
+    pub fn allow_local(mut self, allow_local: bool) -> Self {
+        self.allow_local = allow_local;
+        self
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn permits(&self, address: &IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(address)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(address))
+    }
+
+    /// Check a peer that may have no IP address, see [`AccessList::allow_local`].
+    pub fn permits_peer(&self, address: Option<IpAddr>) -> bool {
+        match address {
+            Some(address) => self.permits(&address),
+            None => self.allow_local || self.is_empty(),
+        }
+    }
+}
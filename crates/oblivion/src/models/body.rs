@@ -0,0 +1,277 @@
+//! # Oblivion Streaming Bodies
+//!
+//! Transfers payloads of any size in chunks with bounded memory. Every chunk is a
+//! message with flag `6` sealed on its own, along with the side sending it, the
+//! number of the body on the session and the index of the chunk. A chunk out of
+//! order or from another body fails to open, and a body is only complete once the
+//! chunk marked as its last one is received, so truncation is detected too.
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::exceptions::Exception;
+
+use super::session::Session;
+
+/// Flag of the messages carrying body chunks.
+const BODY_FLAG: u32 = 6;
+
+/// Largest payload of a single chunk, which bounds the memory used by a transfer.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+fn associated_data(side: u8, body: u64, index: u64) -> [u8; 17] {
+    let mut aad = [0; 17];
+    aad[0] = side;
+    aad[1..9].copy_from_slice(&body.to_be_bytes());
+    aad[9..].copy_from_slice(&index.to_be_bytes());
+    aad
+}
+
+/// Oblivion Body Writer
+///
+/// Sends a body in chunks of [`CHUNK_SIZE`] bytes, the body must end with
+/// [`BodyWriter::finish`] for the peer to accept it.
+///
+/// ```rust
+/// use oblivion::models::session::Session;
+/// use oblivion::utils::gear::Socket;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let (client, server) = tokio::io::duplex(64 * 1024);
+/// let mut client = Session::new_with_header(
+///     "CONNECT /upload Oblivion/2.0".to_string(),
+///     Socket::from_stream(client, None),
+/// )?;
+/// let mut server = Session::new(Socket::from_stream(server, None))?;
+/// tokio::try_join!(client.handshake(0), server.handshake(1))?;
+///
+/// let upload = async {
+///     let mut body = client.send_body();
+///     body.copy_from(&mut &vec![1u8; 1024 * 1024][..]).await?;
+///     body.finish().await
+/// };
+/// let download = async {
+///     let mut received = Vec::new();
+///     server.recv_body().copy_to(&mut received).await?;
+///     anyhow::Ok(received)
+/// };
+/// let ((), received) = tokio::try_join!(upload, download)?;
+/// assert_eq!(received, vec![1u8; 1024 * 1024]);
+/// # Ok(())
+/// # }
+/// ```
+pub struct BodyWriter<'a> {
+    session: &'a Session,
+    body: u64,
+    index: u64,
+    buffer: Vec<u8>,
+}
+
+impl<'a> BodyWriter<'a> {
+    pub(crate) fn new(session: &'a Session, body: u64) -> Self {
+        Self {
+            session,
+            body,
+            index: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE + 1),
+        }
+    }
+
+    /// Append `data` to the body, full chunks are sent right away.
+    pub async fn write(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let len = (CHUNK_SIZE - self.buffer.len()).min(data.len());
+            self.buffer.extend_from_slice(&data[..len]);
+            data = &data[len..];
+            if self.buffer.len() == CHUNK_SIZE {
+                self.flush(false).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Append everything read from `reader` to the body, returning the number of bytes read.
+    pub async fn copy_from<R: AsyncRead + Unpin>(&mut self, reader: &mut R) -> Result<u64> {
+        let mut total = 0;
+        loop {
+            let start = self.buffer.len();
+            self.buffer.resize(CHUNK_SIZE, 0);
+            let read = reader.read(&mut self.buffer[start..]).await;
+            self.buffer.truncate(start + *read.as_ref().unwrap_or(&0));
+            match read? {
+                0 => return Ok(total),
+                len => total += len as u64,
+            }
+            if self.buffer.len() == CHUNK_SIZE {
+                self.flush(false).await?;
+            }
+        }
+    }
+
+    /// Send the last chunk, completing the body.
+    pub async fn finish(mut self) -> Result<()> {
+        self.flush(true).await
+    }
+
+    async fn flush(&mut self, last: bool) -> Result<()> {
+        let mut chunk = Vec::with_capacity(self.buffer.len() + 1);
+        chunk.push(last as u8);
+        chunk.append(&mut self.buffer);
+        let aad = associated_data(self.session.side(true), self.body, self.index);
+        self.session.send_sealed(BODY_FLAG, chunk, &aad).await?;
+        self.index += 1;
+        Ok(())
+    }
+}
+
+/// Oblivion Body Reader
+///
+/// Receives a body sent by a [`BodyWriter`] of the peer chunk by chunk. Readers
+/// must be created in the order the peer sends its bodies, a chunk of another body
+/// fails with [`Exception::BodyTruncated`].
+///
+/// ```rust
+/// use oblivion::exceptions::Exception;
+/// use oblivion::models::session::Session;
+/// use oblivion::utils::gear::Socket;
+/// use tokio::io::AsyncReadExt;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let (client, server) = tokio::io::duplex(64 * 1024);
+/// let mut client = Session::new_with_header(
+///     "CONNECT /upload Oblivion/2.0".to_string(),
+///     Socket::from_stream(client, None),
+/// )?;
+/// let mut server = Session::new(Socket::from_stream(server, None))?;
+/// tokio::try_join!(client.handshake(0), server.handshake(1))?;
+///
+/// for text in ["first", "second"] {
+///     let mut body = client.send_body();
+///     body.write(text.as_bytes()).await?;
+///     body.finish().await?;
+/// }
+///
+/// let mut received = String::new();
+/// server.recv_body().into_async_read().read_to_string(&mut received).await?;
+/// assert_eq!(received, "first");
+///
+/// // Skipping a body leaves the reader of the next one with chunks of another body.
+/// let _skipped = server.recv_body();
+/// let error = server.recv_body().chunk().await.unwrap_err();
+/// assert!(matches!(error.downcast_ref(), Some(Exception::BodyTruncated)));
+/// # Ok(())
+/// # }
+/// ```
+pub struct BodyReader<'a> {
+    session: &'a Session,
+    body: u64,
+    index: u64,
+    done: bool,
+}
+
+impl<'a> BodyReader<'a> {
+    pub(crate) fn new(session: &'a Session, body: u64) -> Self {
+        Self {
+            session,
+            body,
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Receive the next chunk of the body, `None` once the body is complete.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        while !self.done {
+            let aad = associated_data(self.session.side(false), self.body, self.index);
+            let mut chunk = match self.session.recv_sealed(BODY_FLAG, &aad).await {
+                Ok(chunk) if !chunk.is_empty() => chunk,
+                Ok(_) => return Err(Exception::BodyTruncated.into()),
+                Err(error) => return Err(truncated(error)),
+            };
+            self.index += 1;
+            self.done = chunk.remove(0) != 0;
+            if !chunk.is_empty() {
+                return Ok(Some(chunk));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Write the whole body to `writer`, returning the number of bytes written.
+    pub async fn copy_to<W: AsyncWrite + Unpin>(&mut self, writer: &mut W) -> Result<u64> {
+        let mut total = 0;
+        while let Some(chunk) = self.chunk().await? {
+            writer.write_all(&chunk).await?;
+            total += chunk.len() as u64;
+        }
+        writer.flush().await?;
+        Ok(total)
+    }
+
+    /// Receive the chunks of the body as a stream.
+    pub fn into_stream(self) -> impl Stream<Item = Result<Vec<u8>>> + 'a {
+        futures::stream::try_unfold(self, |mut reader| async move {
+            Ok(reader.chunk().await?.map(|chunk| (chunk, reader)))
+        })
+    }
+
+    /// Read the body through [`AsyncRead`], see [`AsyncBodyReader`].
+    pub fn into_async_read(self) -> AsyncBodyReader<'a> {
+        AsyncBodyReader {
+            chunks: self.into_stream().boxed(),
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+/// [`AsyncRead`] adapter of a [`BodyReader`], reaching the end of file once the
+/// body is complete. Errors are wrapped in an [`io::Error`] of kind `Other`.
+pub struct AsyncBodyReader<'a> {
+    chunks: Pin<Box<dyn Stream<Item = Result<Vec<u8>>> + Send + 'a>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl AsyncRead for AsyncBodyReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.position == self.chunk.len() {
+            match ready!(self.chunks.poll_next_unpin(cx)) {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Some(Err(error)) => return Poll::Ready(Err(io::Error::other(error))),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let len = buf.remaining().min(self.chunk.len() - self.position);
+        buf.put_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Report the connection ending in the middle of a body, or a chunk failing to open
+/// because it is out of order or from another body, as a truncated body.
+fn truncated(error: anyhow::Error) -> anyhow::Error {
+    let eof = error
+        .downcast_ref::<io::Error>()
+        .is_some_and(|error| error.kind() == io::ErrorKind::UnexpectedEof);
+    let sealed = matches!(error.downcast_ref(), Some(Exception::DecryptError { .. }));
+    if eof || sealed {
+        Exception::BodyTruncated.into()
+    } else {
+        error
+    }
+}
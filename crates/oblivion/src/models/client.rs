@@ -1,14 +1,22 @@
 //! # Oblivion Client
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Error, Result};
 #[cfg(feature = "serde")]
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::{
-    net::TcpStream,
+    io::{AsyncRead, AsyncWrite},
+    net::{lookup_host, TcpSocket, TcpStream},
     sync::mpsc::{Receiver, Sender},
     task::JoinHandle,
 };
+use tracing::debug;
 
 use crate::exceptions::Exception;
 #[cfg(feature = "pyo3")]
@@ -16,20 +24,31 @@ use crate::exceptions::PyOblivionException;
 
 use crate::utils::gear::Socket;
 use crate::utils::parser::OblivionPath;
+#[cfg(feature = "websocket")]
+use crate::utils::websocket;
 
 #[cfg(feature = "pyo3")]
 use pyo3::prelude::*;
 #[cfg(not(feature = "pyo3"))]
-use serde_json::{from_slice, Value};
+use serde_json::Value;
 #[cfg(feature = "pyo3")]
 use serde_json::{json, Value};
 
+use super::body::{BodyReader, BodyWriter};
+#[cfg(feature = "serde")]
+use super::codec::Codec;
+use super::config::{ClientConfig, CompressionConfig, HeartbeatConfig, RetryPolicy};
+use super::file::FileMeta;
+use super::metrics;
+use super::mux::Mux;
+use super::proxy::Proxy;
 use super::session::Session;
 
 #[cfg_attr(feature = "pyo3", pyclass)]
 #[derive(Debug, Default)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Response {
+    /// Content type the server tagged the response with, if any.
     #[cfg_attr(feature = "pyo3", pyo3(get))]
     pub header: Option<String>,
     #[cfg_attr(feature = "pyo3", pyo3(get))]
@@ -38,6 +57,12 @@ pub struct Response {
     pub entrance: Option<String>,
     #[cfg_attr(feature = "pyo3", pyo3(get))]
     pub flag: u32,
+    /// Status set by the handler, `200` unless set otherwise.
+    #[cfg_attr(feature = "pyo3", pyo3(get))]
+    pub status: u32,
+    /// Headers set by the handler by lowercase name.
+    #[cfg_attr(feature = "pyo3", pyo3(get))]
+    pub headers: HashMap<String, String>,
 }
 
 #[cfg(not(feature = "pyo3"))]
@@ -53,34 +78,71 @@ impl Response {
             content,
             entrance,
             flag,
+            status: 200,
+            headers: HashMap::new(),
         }
     }
 
+    /// Whether the status is a success, between `200` and `299`.
+    #[inline]
+    pub fn ok(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    #[inline]
+    pub fn status(&self) -> u32 {
+        self.status
+    }
+
+    /// Value of the header `name`, which is case insensitive.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
     pub fn text(&self) -> Result<String> {
         Ok(String::from_utf8(self.content.to_vec())?)
     }
 
     pub fn json(&self) -> Result<Value> {
-        Ok(from_slice(&self.content)?)
+        #[cfg(feature = "serde")]
+        return Ok(self.decode()?);
+        #[cfg(not(feature = "serde"))]
+        Ok(serde_json::from_slice(&self.content)?)
+    }
+
+    /// Codec the content was encoded with according to its content type, JSON by default.
+    #[cfg(feature = "serde")]
+    pub fn codec(&self) -> Codec {
+        self.header
+            .as_deref()
+            .and_then(Codec::from_content_type)
+            .unwrap_or_default()
+    }
+
+    /// Deserialize the content with its codec, see [`Response::codec`].
+    #[cfg(feature = "serde")]
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, Exception> {
+        self.codec().decode(&self.content)
     }
 }
 
 impl PartialEq for Response {
     fn eq(&self, other: &Self) -> bool {
-        if self.entrance.is_none() {
-            self.header == other.header
-                && self.content == other.content
-                && self.entrance == other.entrance
-                && self.flag == other.flag
-        } else if other.entrance.is_none() {
-            false
-        } else {
-            self.header == other.header
-                && self.content == other.content
-                && self.entrance.as_ref().unwrap().trim_end_matches("/")
-                    == other.entrance.as_ref().unwrap().trim_end_matches("/")
-                && self.flag == other.flag
-        }
+        let entrance_eq = match (&self.entrance, &other.entrance) {
+            (None, None) => true,
+            (Some(entrance), Some(other_entrance)) => {
+                entrance.trim_end_matches("/") == other_entrance.trim_end_matches("/")
+            }
+            _ => false,
+        };
+        entrance_eq
+            && self.header == other.header
+            && self.content == other.content
+            && self.flag == other.flag
+            && self.status == other.status
+            && self.headers == other.headers
     }
 }
 
@@ -112,6 +174,111 @@ impl Response {
     }
 }
 
+/// Oblivion Request
+///
+/// Headers and body sent along with a request, encrypted like any other message.
+/// Handlers read them from `OblivionRequest` before they run.
+///
+/// ```rust
+/// use oblivion::models::client::Request;
+/// use serde_json::json;
+///
+/// let request = Request::new()
+///     .header("Authorization", "Bearer token")
+///     .json(&json!({ "name": "oblivion" }));
+///
+/// assert_eq!(request.headers["authorization"], "Bearer token");
+/// assert_eq!(request.headers["content-type"], "application/json");
+/// assert_eq!(request.body, br#"{"name":"oblivion"}"#);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Request {
+    /// Headers by lowercase name.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the header `name`, which is case insensitive, to `value`.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers
+            .insert(name.to_ascii_lowercase(), value.to_string());
+        self
+    }
+
+    /// Send `body` as is.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Send `text` as the body, tagged as plain text.
+    pub fn text(self, text: &str) -> Self {
+        self.header("content-type", "text/plain; charset=utf-8")
+            .body(text)
+    }
+
+    /// Send `json` as the body, tagged as JSON.
+    pub fn json(self, json: &Value) -> Self {
+        self.header("content-type", "application/json")
+            .body(json.to_string())
+    }
+
+    /// Whether there is nothing to send along with the request.
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.body.is_empty()
+    }
+
+    /// Encode the request of `entrance`: the entrance and the headers as JSON end at
+    /// a line feed each, the body follows them.
+    pub(crate) fn encode(&self, entrance: &str) -> Vec<u8> {
+        let headers = serde_json::to_vec(&self.headers).unwrap_or_default();
+        let mut data = Vec::with_capacity(entrance.len() + headers.len() + 2 + self.body.len());
+        data.extend_from_slice(entrance.as_bytes());
+        data.push(b'\n');
+        data.extend(headers);
+        data.push(b'\n');
+        data.extend_from_slice(&self.body);
+        data
+    }
+
+    /// Decode a request encoded by [`Request::encode`] along with its entrance.
+    pub(crate) fn decode(mut data: Vec<u8>) -> Result<(String, Self), Exception> {
+        let invalid = || Exception::InvalidHeader("malformed request".to_string());
+        let mut line = || {
+            let split = data
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .ok_or_else(invalid)?;
+            let line = data.drain(..=split).take(split).collect::<Vec<u8>>();
+            String::from_utf8(line).map_err(|_| invalid())
+        };
+        let entrance = line()?;
+        let headers = serde_json::from_str::<HashMap<String, String>>(&line()?)
+            .map_err(|_| invalid())?
+            .into_iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .collect();
+        Ok((
+            entrance,
+            Self {
+                headers,
+                body: data,
+            },
+        ))
+    }
+}
+
+impl From<Vec<u8>> for Request {
+    fn from(body: Vec<u8>) -> Self {
+        Self::new().body(body)
+    }
+}
+
 pub struct Client {
     pub entrance: String,
     pub path: OblivionPath,
@@ -121,24 +288,174 @@ pub struct Client {
 }
 
 impl Client {
+    /// Connect to `entrance`, through the proxy configured by the environment if any.
+    ///
+    /// See [`Proxy::from_env`] for the environment variables read and [`ClientBuilder`]
+    /// to configure timeouts, socket options and retries.
     pub async fn connect(entrance: &str) -> Result<Self> {
-        let path = OblivionPath::new(entrance)?;
-        let header = format!("CONNECT {} Oblivion/2.0", path.get_entrance());
-
-        let tcp = match TcpStream::connect(format!("{}:{}", path.get_host(), path.get_port())).await
-        {
-            Ok(tcp) => {
-                tcp.set_ttl(20)?;
-                tcp.set_nodelay(true)?;
-                socket2::SockRef::from(&tcp).set_keepalive(true)?;
-                tcp
-            }
-            Err(_) => return Err(Error::from(Exception::ConnectionRefusedError)),
+        ClientBuilder::new().connect(entrance).await
+    }
+
+    /// Connect to `entrance` and send `request` along with it, see [`Client::connect`].
+    ///
+    /// ```rust
+    /// use oblivion::models::client::{Client, Request};
+    /// use oblivion::models::render::BaseResponse;
+    /// use oblivion::models::router::{RoutePath, RouteType, Router};
+    /// use oblivion::models::server::Server;
+    /// use oblivion::models::session::Session;
+    /// use oblivion::types::ServerResponse;
+    /// use oblivion_codegen::async_route;
+    /// use tokio::net::TcpListener;
+    ///
+    /// #[async_route]
+    /// fn greet(session: Session) -> ServerResponse {
+    ///     let name = session.request.get_json()?["name"].to_string();
+    ///     let greeting = session.request.get_header("Greeting").unwrap_or("Hello");
+    ///     Ok(BaseResponse::TextResponse(format!("{}, {}!", greeting, name)))
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let mut router = Router::new();
+    /// router.route(RoutePath::new("/greet", RouteType::Path), greet);
+    /// let listener = TcpListener::bind("127.0.0.1:0").await?;
+    /// let server = Server::builder(router).listener(listener).build();
+    /// let address = server.local_addr().unwrap();
+    /// tokio::spawn(async move { server.run().await });
+    ///
+    /// let request = Request::new()
+    ///     .header("Greeting", "Welcome")
+    ///     .json(&serde_json::json!({ "name": "Oblivion" }));
+    /// let client = Client::connect_with(&format!("{}/greet", address), request).await?;
+    /// assert_eq!(client.recv().await?.text()?, r#"Welcome, "Oblivion"!"#);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_with(entrance: &str, request: Request) -> Result<Self> {
+        ClientBuilder::new().connect_with(entrance, request).await
+    }
+
+    /// Start configuring a client, see [`ClientBuilder`].
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Connect to `entrance` through `proxy`, ignoring the proxy environment variables.
+    pub async fn connect_with_proxy(entrance: &str, proxy: &Proxy) -> Result<Self> {
+        ClientBuilder::new()
+            .proxy(proxy.clone())
+            .connect(entrance)
+            .await
+    }
+
+    /// Connect through the WebSocket listener at `url` and request `entrance`, a path such as `/welcome`.
+    ///
+    /// `oblivion+ws://host:port/entrance` locations passed to [`Client::connect`] use the
+    /// default WebSocket path, this allows any other path, e.g. behind a reverse proxy.
+    #[cfg(feature = "websocket")]
+    pub async fn connect_websocket(url: &str, entrance: &str) -> Result<Self> {
+        let socket = websocket::connect(url).await?;
+        let path = OblivionPath::local(entrance);
+        Self::handshake(
+            entrance,
+            path,
+            socket,
+            &Request::default(),
+            None,
+            &mut false,
+        )
+        .await
+    }
+
+    /// Open a session over a pre-connected stream and request `entrance`, a path such as `/welcome`.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use oblivion::models::client::Client;
+    /// use oblivion::models::config::ServerConfig;
+    /// use oblivion::models::router::Router;
+    /// use oblivion::models::server::handle;
+    /// use oblivion::utils::gear::Socket;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let (client, server) = tokio::io::duplex(4096);
+    /// let router = Arc::new(Router::new());
+    /// let config = Arc::new(ServerConfig::default());
+    /// tokio::spawn(handle(router, config, None, Socket::from_stream(server, None)));
+    ///
+    /// let client = Client::from_stream(client, "/welcome").await?;
+    /// assert!(client.recv().await?.text()?.contains("is not found"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_stream<S>(stream: S, entrance: &str) -> Result<Self>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let path = OblivionPath::local(entrance);
+        let socket = Socket::from_stream(stream, None);
+        Self::handshake(
+            entrance,
+            path,
+            socket,
+            &Request::default(),
+            None,
+            &mut false,
+        )
+        .await
+    }
+
+    /// Open the session, requests with headers or a body use the `POST` method and
+    /// send them right after the key exchange.
+    ///
+    /// Accepted compression encodings are offered in the `accept-encoding` header.
+    /// `sent` is set once the request frame starts being sent, after which the
+    /// server may already be handling it.
+    async fn handshake(
+        entrance: &str,
+        path: OblivionPath,
+        socket: Socket,
+        request: &Request,
+        compression: Option<CompressionConfig>,
+        sent: &mut bool,
+    ) -> Result<Self> {
+        let mut session = Session::new_with_header(String::new(), socket)?;
+        session.set_compression(compression);
+        let request = &match session.accepted_encodings() {
+            Some(accepted) => request.clone().header("accept-encoding", &accepted),
+            None => request.clone(),
         };
 
-        let mut session = Session::new_with_header(header, Socket::new(tcp))?;
+        let method = if request.is_empty() {
+            "CONNECT"
+        } else {
+            "POST"
+        };
+        session.header = format!("{} {} Oblivion/2.1", method, path.get_entrance());
 
-        session.handshake(0).await?;
+        let handshake = async {
+            session.handshake(0).await?;
+            if !request.is_empty() {
+                *sent = true;
+                session
+                    .send_frame(4, request.encode(path.get_entrance()))
+                    .await?;
+            }
+            if request.headers.contains_key("accept-encoding") {
+                session.recv_encoding().await?;
+            }
+            anyhow::Ok(())
+        };
+        if let Err(error) = handshake.await {
+            metrics::counter(
+                metrics::HANDSHAKE_FAILURES,
+                &[("side", "client"), ("cause", metrics::cause(&error))],
+                1,
+            );
+            return Err(error);
+        }
 
         let (sender, receiver) = tokio::sync::mpsc::channel(1024);
         Ok(Self {
@@ -171,11 +488,440 @@ impl Client {
         self.session.send_json(json).await
     }
 
+    /// Send `value` serialized as JSON, see [`Session::send_as`].
+    #[cfg(feature = "serde")]
+    pub async fn send_as<T: Serialize + ?Sized>(&self, value: &T) -> Result<()> {
+        self.session.send_as(value).await
+    }
+
+    /// Send `value` encoded with `codec`, see [`Session::send_with`].
+    #[cfg(feature = "serde")]
+    pub async fn send_with<T: Serialize + ?Sized>(&self, codec: Codec, value: &T) -> Result<()> {
+        self.session.send_with(codec, value).await
+    }
+
+    /// Receive the next response and deserialize it, see [`Session::recv_as`].
+    #[cfg(feature = "serde")]
+    pub async fn recv_as<T: DeserializeOwned>(&self) -> Result<T> {
+        self.session.recv_as().await
+    }
+
     pub async fn recv(&self) -> Result<Response> {
         self.session.recv().await
     }
 
+    /// Request another `entrance`, a path such as `/welcome`, on the same session with
+    /// `request`, either a [`Request`] or a body.
+    ///
+    /// The response to the previous request must have been received and the server
+    /// must keep sessions open, see `ServerBuilder::persistent`.
+    ///
+    /// ```rust
+    /// use oblivion::models::client::Client;
+    /// use oblivion::models::router::Router;
+    /// use oblivion::models::server::Server;
+    /// use tokio::net::TcpListener;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let listener = TcpListener::bind("127.0.0.1:0").await?;
+    /// let server = Server::builder(Router::new())
+    ///     .listener(listener)
+    ///     .persistent(true)
+    ///     .build();
+    /// let address = server.local_addr().unwrap();
+    /// tokio::spawn(async move { server.run().await });
+    ///
+    /// let client = Client::connect(&format!("{}/first", address)).await?;
+    /// assert_eq!(client.recv().await?.flag, 3);
+    /// client.request("/second", b"body".to_vec()).await?;
+    /// assert!(client.recv().await?.text()?.contains("is not found"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn request(&self, entrance: &str, request: impl Into<Request>) -> Result<()> {
+        self.session.request(entrance, request).await
+    }
+
+    /// Start sending a body in chunks, see [`BodyWriter`].
+    pub fn send_body(&self) -> BodyWriter<'_> {
+        self.session.send_body()
+    }
+
+    /// Start receiving the next body sent by the server, see [`BodyReader`].
+    pub fn recv_body(&self) -> BodyReader<'_> {
+        self.session.recv_body()
+    }
+
+    /// Receive a file sent by the server, see [`Session::recv_file`].
+    pub async fn recv_file<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<FileMeta> {
+        self.session.recv_file(writer).await
+    }
+
+    /// Multiplex streams over the session, the server handler must multiplex it too.
+    pub fn mux(&self) -> Mux {
+        Mux::client(Arc::clone(&self.session))
+    }
+
     pub async fn close(&self) -> Result<()> {
         self.session.close().await
     }
+
+    /// Close the session with `code` and `reason`, see [`Session::close_with`].
+    pub async fn close_with(&self, code: u32, reason: &str) -> Result<()> {
+        self.session.close_with(code, reason).await
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ProxySetting {
+    Env,
+    Direct,
+    Proxy(Proxy),
+}
+
+/// Oblivion Client Builder
+///
+/// Configures the [`ClientConfig`] and proxy of a connection. Every connection
+/// error is reported with a precise [`Exception`], such as `DnsError`,
+/// `ConnectTimeout`, `ConnectionRefusedError` or `ConnectionReset`.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use oblivion::exceptions::Exception;
+/// # use oblivion::models::client::Client;
+/// # use oblivion::models::config::RetryPolicy;
+/// # #[tokio::main]
+/// # async fn main() {
+/// let error = Client::builder()
+///     .connect_timeout(Some(Duration::from_secs(3)))
+///     .handshake_timeout(Some(Duration::from_secs(5)))
+///     .retry(RetryPolicy::new(2))
+///     .no_proxy()
+///     .connect("127.0.0.1:9/welcome")
+///     .await
+///     .err()
+///     .unwrap();
+///
+/// assert_eq!(
+///     error.downcast_ref::<Exception>(),
+///     Some(&Exception::ConnectionRefusedError)
+/// );
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    config: ClientConfig,
+    proxy: ProxySetting,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClientBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: ClientConfig::default(),
+            proxy: ProxySetting::Env,
+        }
+    }
+
+    /// Replace the whole configuration.
+    pub fn config(mut self, config: ClientConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.config.ttl = ttl;
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.config.nodelay = nodelay;
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: bool) -> Self {
+        self.config.keepalive = keepalive;
+        self
+    }
+
+    /// Idle time before keepalive probes are sent.
+    pub fn keepalive_time(mut self, time: Option<Duration>) -> Self {
+        self.config.keepalive_time = time;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    pub fn handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    /// Bind the connection to a local address, only addresses of the same family are tried.
+    pub fn local_address(mut self, address: Option<SocketAddr>) -> Self {
+        self.config.local_address = address;
+        self
+    }
+
+    /// Accept messages compressed with one of the configured encodings.
+    pub fn compression(mut self, compression: Option<CompressionConfig>) -> Self {
+        self.config.compression = compression;
+        self
+    }
+
+    /// Ping the server as configured, closing the session once it stopped answering.
+    pub fn heartbeat(mut self, heartbeat: Option<HeartbeatConfig>) -> Self {
+        self.config.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    /// Connect through `proxy` instead of the proxy configured by the environment.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = ProxySetting::Proxy(proxy);
+        self
+    }
+
+    /// Connect directly, ignoring the proxy environment variables.
+    pub fn no_proxy(mut self) -> Self {
+        self.proxy = ProxySetting::Direct;
+        self
+    }
+
+    /// Connect to `entrance`, retrying transient failures as configured.
+    pub async fn connect(&self, entrance: &str) -> Result<Client> {
+        self.connect_with(entrance, Request::default()).await
+    }
+
+    /// Connect to `entrance` and send `request` along with it, retrying transient
+    /// failures as configured.
+    pub async fn connect_with(&self, entrance: &str, request: Request) -> Result<Client> {
+        if let Some(heartbeat) = &self.config.heartbeat {
+            heartbeat.validate()?;
+        }
+        let retry = &self.config.retry;
+        let mut attempt = 0;
+        loop {
+            let mut sent = false;
+            match self.attempt(entrance, &request, &mut sent).await {
+                Ok(client) => return Ok(client),
+                Err(error)
+                    if attempt < retry.max_retries
+                        && retryable(&error)
+                        && (!sent || request.body.is_empty() || retry.retry_sent) =>
+                {
+                    let backoff = retry.backoff(attempt);
+                    debug!(%error, attempt, ?backoff, "connection failed, retrying");
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    async fn attempt(&self, entrance: &str, request: &Request, sent: &mut bool) -> Result<Client> {
+        let path = OblivionPath::new(entrance)?;
+        let mut socket = match self.config.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.open(&path))
+                .await
+                .unwrap_or_else(|_| Err(Exception::ConnectTimeout { timeout }.into()))?,
+            None => self.open(&path).await?,
+        };
+        socket.set_read_timeout(self.config.read_timeout);
+
+        let handshake = Client::handshake(
+            entrance,
+            path,
+            socket,
+            request,
+            self.config.compression.clone(),
+            sent,
+        );
+        let client = match self.config.handshake_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, handshake)
+                .await
+                .unwrap_or_else(|_| Err(Exception::HandshakeTimeout { timeout }.into())),
+            None => handshake.await,
+        }
+        .map_err(reset)?;
+        if let Some(heartbeat) = &self.config.heartbeat {
+            client.session.start_heartbeat(heartbeat.clone())?;
+        }
+        Ok(client)
+    }
+
+    async fn open(&self, path: &OblivionPath) -> Result<Socket> {
+        match path.get_socket_path() {
+            #[cfg(unix)]
+            Some(socket_path) => Ok(Socket::new_unix(
+                UnixStream::connect(socket_path)
+                    .await
+                    .map_err(connect_error)?,
+            )),
+            #[cfg(not(unix))]
+            Some(socket_path) => Err(Error::from(Exception::InvalidOblivion {
+                entrance: socket_path.to_string(),
+            })),
+            #[cfg(feature = "websocket")]
+            None if path.get_protocol() == "oblivion+ws" => {
+                let url = format!(
+                    "ws://{}:{}{}",
+                    path.get_host(),
+                    path.get_port(),
+                    websocket::DEFAULT_WEBSOCKET_PATH
+                );
+                let tcp = self.tcp(path).await?;
+                websocket::client(&url, tcp).await.map_err(|error| {
+                    Error::from(Exception::ConnectFailed {
+                        reason: error.to_string(),
+                    })
+                })
+            }
+            None => Ok(Socket::new(self.tcp(path).await?)),
+        }
+    }
+
+    /// Open the TCP stream to the host of `path`, directly or through the proxy.
+    async fn tcp(&self, path: &OblivionPath) -> Result<TcpStream> {
+        let host = path.get_host();
+        let port: u16 = path
+            .get_port()
+            .parse()
+            .map_err(|_| Exception::InvalidOblivion {
+                entrance: format!("{}:{}", host, path.get_port()),
+            })?;
+        let proxy = match &self.proxy {
+            ProxySetting::Env => Proxy::from_env(host)?,
+            ProxySetting::Direct => None,
+            ProxySetting::Proxy(proxy) => Some(proxy.clone()),
+        };
+        let tcp = match proxy {
+            Some(proxy) => {
+                let mut tcp = self.dial(proxy.address()).await?;
+                proxy.tunnel(&mut tcp, host, port).await.map_err(reset)?;
+                tcp
+            }
+            None => self.dial(&format!("{}:{}", host, port)).await?,
+        };
+
+        tcp.set_ttl(self.config.ttl)?;
+        tcp.set_nodelay(self.config.nodelay)?;
+        let socket = socket2::SockRef::from(&tcp);
+        socket.set_keepalive(self.config.keepalive)?;
+        if let (true, Some(time)) = (self.config.keepalive, self.config.keepalive_time) {
+            socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(time))?;
+        }
+        Ok(tcp)
+    }
+
+    /// Resolve `address` and connect to the first address accepting the connection.
+    async fn dial(&self, address: &str) -> Result<TcpStream> {
+        let dns_error = |reason: String| Exception::DnsError {
+            host: address.to_string(),
+            reason,
+        };
+        let addresses: Vec<SocketAddr> = lookup_host(address)
+            .await
+            .map_err(|error| dns_error(error.to_string()))?
+            .collect();
+        if addresses.is_empty() {
+            return Err(dns_error("no addresses found".to_string()).into());
+        }
+
+        let local = self.config.local_address;
+        let mut last_error = None;
+        for address in addresses {
+            if local.is_some_and(|local| local.is_ipv4() != address.is_ipv4()) {
+                continue;
+            }
+            let socket = if address.is_ipv4() {
+                TcpSocket::new_v4()?
+            } else {
+                TcpSocket::new_v6()?
+            };
+            if let Some(local) = local {
+                socket.bind(local)?;
+            }
+            match socket.connect(address).await {
+                Ok(tcp) => return Ok(tcp),
+                Err(error) => last_error = Some(error),
+            }
+        }
+        Err(match last_error {
+            Some(error) => connect_error(error),
+            None => Error::from(Exception::ConnectFailed {
+                reason: "no address of the local address family".to_string(),
+            }),
+        })
+    }
+}
+
+fn connect_error(error: io::Error) -> Error {
+    Error::from(match error.kind() {
+        io::ErrorKind::ConnectionRefused => Exception::ConnectionRefusedError,
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted => {
+            Exception::ConnectionReset
+        }
+        _ => Exception::ConnectFailed {
+            reason: error.to_string(),
+        },
+    })
+}
+
+/// Report the peer dropping the connection mid-handshake as a reset.
+fn reset(error: Error) -> Error {
+    match error.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe,
+        ) => Error::from(Exception::ConnectionReset),
+        _ => error,
+    }
+}
+
+/// Whether a failed connection attempt may succeed when retried.
+fn retryable(error: &Error) -> bool {
+    match error.downcast_ref::<Exception>() {
+        Some(
+            Exception::ConnectionRefusedError
+            | Exception::ConnectionReset
+            | Exception::ConnectTimeout { .. }
+            | Exception::HandshakeTimeout { .. }
+            | Exception::ConnectFailed { .. },
+        ) => true,
+        Some(_) => false,
+        None => error.is::<io::Error>(),
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let side = [("side", "client")];
+        let socket = &self.session.socket;
+        metrics::counter(metrics::BYTES_SENT, &side, socket.bytes_written());
+        metrics::counter(metrics::BYTES_RECEIVED, &side, socket.bytes_read());
+    }
 }
@@ -0,0 +1,113 @@
+//! # Oblivion Codecs
+//!
+//! Encodings of structured payloads. A message encoded with anything but JSON is
+//! tagged with the content type of its codec, so that receivers decode it the same
+//! way without being told beforehand.
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::exceptions::Exception;
+
+/// Payload Codec
+///
+/// MessagePack and CBOR need the `msgpack` and `cbor` features respectively, without
+/// them their codecs are still recognized but fail to encode and decode.
+///
+/// ```rust
+/// use oblivion::models::codec::Codec;
+///
+/// let codec = Codec::from_content_type("application/json; charset=utf-8").unwrap();
+/// let bytes = codec.encode(&vec![1, 2, 3])?;
+///
+/// assert_eq!(codec, Codec::Json);
+/// assert_eq!(bytes, b"[1,2,3]");
+/// assert_eq!(codec.decode::<Vec<u8>>(&bytes)?, vec![1, 2, 3]);
+/// assert!(codec.decode::<String>(&bytes).is_err());
+///
+/// let codec = Codec::from_content_type("application/cbor").unwrap();
+/// # #[cfg(not(feature = "cbor"))]
+/// # assert!(!codec.is_supported() && codec.encode(&1).is_err());
+/// # #[cfg(feature = "cbor")]
+/// assert_eq!(codec.decode::<u8>(&codec.encode(&1)?)?, 1);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Codec {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Codec {
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Json => true,
+            Self::MessagePack => cfg!(feature = "msgpack"),
+            Self::Cbor => cfg!(feature = "cbor"),
+        }
+    }
+
+    /// Content type tagging messages encoded with the codec.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+        }
+    }
+
+    /// Codec of `content_type`, parameters such as `charset` are ignored.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+        match essence.to_ascii_lowercase().as_str() {
+            "application/json" => Some(Self::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Self::MessagePack)
+            }
+            "application/cbor" => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => Ok(serde_json::to_vec(value)?),
+            // Structs are encoded as maps so that fields may be added or reordered.
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(value, &mut bytes)?;
+                Ok(bytes)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unsupported().into()),
+        }
+    }
+
+    /// Decode `bytes`, failing with `Exception::DeserializeError` unless they hold a `T`.
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Exception> {
+        let decoded = match self {
+            Self::Json => serde_json::from_slice(bytes).map_err(|error| error.to_string()),
+            #[cfg(feature = "msgpack")]
+            Self::MessagePack => rmp_serde::from_slice(bytes).map_err(|error| error.to_string()),
+            #[cfg(feature = "cbor")]
+            Self::Cbor => ciborium::from_reader(bytes).map_err(|error| error.to_string()),
+            #[allow(unreachable_patterns)]
+            _ => return Err(self.unsupported()),
+        };
+        decoded.map_err(|reason| Exception::DeserializeError {
+            target: std::any::type_name::<T>().to_string(),
+            reason,
+        })
+    }
+
+    fn unsupported(&self) -> Exception {
+        Exception::UnsupportedMethod {
+            method: format!("{} codec", self.content_type()),
+        }
+    }
+}
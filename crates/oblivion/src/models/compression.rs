@@ -0,0 +1,292 @@
+//! # Oblivion Compression
+//!
+//! Compresses messages before they are sealed. Clients list the encodings they
+//! accept in the `accept-encoding` header of their request, the server picks the
+//! first of its own encodings the client accepts and announces it, or none, with a
+//! message with flag `9`. Each side then compresses the messages it sends with that encoding
+//! once they reach the minimum size, marking them with the bit of the encoding in
+//! their flag.
+//!
+//! ## Security
+//!
+//! Compression leaks how much the content of a message repeats itself through its
+//! size, even once encrypted. An attacker able to inject data into a message that
+//! also holds a secret, and to observe the size of the messages, can recover the
+//! secret byte by byte by guessing it, as done against TLS and HTTP by the CRIME and
+//! BREACH attacks. Keep compression disabled for sessions mixing secrets with data
+//! controlled by someone else, or send such messages with
+//! `Session::send_uncompressed`.
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use anyhow::Result;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::exceptions::Exception;
+
+use super::config::CompressionConfig;
+
+/// Flag of the message announcing the encoding chosen by the server.
+pub(crate) const ENCODING_FLAG: u32 = 9;
+
+/// Bits of a flag marking the encoding of a compressed message.
+pub(crate) const ENCODING_MASK: u32 = 0xff00;
+
+/// Compression Encoding
+///
+/// Each encoding is only available with the feature of the same name, see
+/// [`Encoding::supported`].
+///
+/// ```rust
+/// use oblivion::models::compression::Encoding;
+///
+/// let encoding: Encoding = "zstd".parse()?;
+/// let data = vec![0; 4096];
+///
+/// # #[cfg(feature = "zstd")]
+/// # {
+/// assert!(encoding.is_supported());
+/// let compressed = encoding.compress(&data)?;
+/// assert!(compressed.len() < data.len());
+/// assert!(encoding.decompress(&compressed, 1024).is_err());
+/// assert_eq!(encoding.decompress(&compressed, 4096)?, data);
+/// # }
+/// # #[cfg(not(feature = "zstd"))]
+/// # assert!(!encoding.is_supported() && encoding.compress(&data).is_err());
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Encoding {
+    Zstd,
+    Deflate,
+}
+
+impl Encoding {
+    /// Encodings compiled in.
+    pub fn supported() -> Vec<Self> {
+        [Self::Zstd, Self::Deflate]
+            .into_iter()
+            .filter(Self::is_supported)
+            .collect()
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            Self::Zstd => cfg!(feature = "zstd"),
+            Self::Deflate => cfg!(feature = "deflate"),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    fn bit(&self) -> u32 {
+        match self {
+            Self::Zstd => 0x100,
+            Self::Deflate => 0x200,
+        }
+    }
+
+    fn from_bit(bit: u32) -> Option<Self> {
+        match bit {
+            0x100 => Some(Self::Zstd),
+            0x200 => Some(Self::Deflate),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        self.encoder(data)?.read_to_end(&mut compressed)?;
+        Ok(compressed)
+    }
+
+    /// Decompress `data`, failing with `Exception::DataTooLarge` once it exceeds `limit` bytes.
+    pub fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+        // Stop reading right past the limit, whatever the compressed data claims.
+        let mut decompressed = Vec::new();
+        self.decoder(data)?
+            .take(limit as u64 + 1)
+            .read_to_end(&mut decompressed)?;
+        if decompressed.len() > limit {
+            return Err(Exception::DataTooLarge { size: limit }.into());
+        }
+        Ok(decompressed)
+    }
+
+    #[cfg_attr(
+        not(any(feature = "zstd", feature = "deflate")),
+        allow(unused_variables)
+    )]
+    fn encoder<'a>(&self, data: &'a [u8]) -> Result<Box<dyn Read + 'a>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Box::new(zstd::stream::read::Encoder::new(
+                data,
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+            )?)),
+            #[cfg(feature = "deflate")]
+            Self::Deflate => Ok(Box::new(flate2::read::DeflateEncoder::new(
+                data,
+                flate2::Compression::default(),
+            ))),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unsupported().into()),
+        }
+    }
+
+    #[cfg_attr(
+        not(any(feature = "zstd", feature = "deflate")),
+        allow(unused_variables)
+    )]
+    fn decoder<'a>(&self, data: &'a [u8]) -> Result<Box<dyn Read + 'a>> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(data)?)),
+            #[cfg(feature = "deflate")]
+            Self::Deflate => Ok(Box::new(flate2::read::DeflateDecoder::new(data))),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unsupported().into()),
+        }
+    }
+
+    fn unsupported(&self) -> Exception {
+        Exception::UnsupportedMethod {
+            method: format!("{} compression", self.name()),
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = Exception;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "zstd" => Ok(Self::Zstd),
+            "deflate" => Ok(Self::Deflate),
+            _ => Err(Exception::InvalidConfig(format!(
+                "unknown compression encoding: {}",
+                name
+            ))),
+        }
+    }
+}
+
+/// Compression state of a session, shared by the sessions of its requests.
+pub(crate) struct Compression {
+    config: Option<CompressionConfig>,
+    /// Bit of the encoding in use, `0` until one is negotiated.
+    encoding: AtomicU32,
+}
+
+impl Compression {
+    pub(crate) fn new(config: Option<CompressionConfig>) -> Self {
+        Self {
+            config,
+            encoding: AtomicU32::new(0),
+        }
+    }
+
+    /// Value of the `accept-encoding` header listing the encodings accepted.
+    pub(crate) fn accepted(&self) -> Option<String> {
+        let accepted = self
+            .config
+            .as_ref()?
+            .encodings
+            .iter()
+            .filter(|encoding| encoding.is_supported())
+            .map(Encoding::name)
+            .collect::<Vec<_>>();
+        (!accepted.is_empty()).then(|| accepted.join(", "))
+    }
+
+    /// Pick the first encoding of the configuration listed in `accepted`.
+    pub(crate) fn negotiate(&self, accepted: &str) -> Option<Encoding> {
+        let accepted = accepted
+            .split(',')
+            .filter_map(|name| name.parse().ok())
+            .collect::<Vec<Encoding>>();
+        let encoding = *self
+            .config
+            .as_ref()?
+            .encodings
+            .iter()
+            .find(|encoding| encoding.is_supported() && accepted.contains(encoding))?;
+        self.encoding.store(encoding.bit(), Ordering::Release);
+        Some(encoding)
+    }
+
+    /// Use the encoding named in `content` of an announcement from the server, none if empty.
+    pub(crate) fn announced(&self, content: &[u8]) -> Result<()> {
+        if content.is_empty() {
+            return Ok(());
+        }
+        let encoding = String::from_utf8_lossy(content).parse::<Encoding>()?;
+        if !encoding.is_supported() {
+            return Err(encoding.unsupported().into());
+        }
+        self.encoding.store(encoding.bit(), Ordering::Release);
+        Ok(())
+    }
+
+    /// Compress `data` of a message with `flag` when an encoding is in use and it
+    /// is large enough, returning the flag marked with the encoding.
+    pub(crate) fn compress(&self, flag: u32, data: Vec<u8>) -> Result<(u32, Vec<u8>)> {
+        let (Some(encoding), Some(config)) = (
+            Encoding::from_bit(self.encoding.load(Ordering::Acquire)),
+            &self.config,
+        ) else {
+            return Ok((flag, data));
+        };
+        if flag == ENCODING_FLAG || data.len() < config.min_size {
+            return Ok((flag, data));
+        }
+        let compressed = encoding.compress(&data)?;
+        match compressed.len() < data.len() {
+            true => Ok((flag | encoding.bit(), compressed)),
+            false => Ok((flag, data)),
+        }
+    }
+
+    /// Decompress `data` of a message with `flag` up to `limit` bytes, or less if
+    /// configured, returning the flag without its encoding.
+    ///
+    /// Only the encoding negotiated is accepted.
+    pub(crate) fn decompress(
+        &self,
+        flag: u32,
+        data: Vec<u8>,
+        limit: usize,
+    ) -> Result<(u32, Vec<u8>)> {
+        let bit = flag & ENCODING_MASK;
+        if bit == 0 {
+            return Ok((flag, data));
+        }
+        if bit != self.encoding.load(Ordering::Acquire) {
+            return Err(Exception::InvalidHeader(format!("unexpected encoding {:#x}", bit)).into());
+        }
+        let encoding = Encoding::from_bit(bit)
+            .filter(Encoding::is_supported)
+            .ok_or_else(|| Exception::InvalidHeader(format!("unknown encoding {:#x}", bit)))?;
+        let limit = self
+            .config
+            .as_ref()
+            .map_or(limit, |config| config.max_decompressed_size.min(limit));
+        Ok((flag & !ENCODING_MASK, encoding.decompress(&data, limit)?))
+    }
+}
@@ -0,0 +1,709 @@
+//! # Oblivion Configuration
+//!
+//! Tunable knobs for Oblivion servers and clients, loadable from code, environment variables or TOML.
+use std::env;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::exceptions::Exception;
+
+use super::access::{AccessLogConfig, AccessLogFormat};
+use super::acl::{AccessList, Cidr};
+use super::compression::Encoding;
+use super::limiter::RateLimit;
+
+/// Prefix of all environment variables read by [`ServerConfig::from_env`].
+pub const ENV_PREFIX: &str = "OBLIVION_";
+
+/// Oblivion Server Configuration
+///
+/// Holds the socket options applied to every accepted connection as well as the
+/// limits guarding the server. The default value matches the historical behavior
+/// of `Server::new`, with a handshake deadline and a message size cap added.
+///
+/// Durations are expressed in seconds when loaded from the environment or TOML,
+/// where `"none"` disables an optional one.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use oblivion::models::config::ServerConfig;
+///
+/// let config = ServerConfig::default();
+///
+/// assert_eq!(config.ttl, 20);
+/// assert_eq!(config.linger, Some(Duration::ZERO));
+/// assert_eq!(config.max_connections, None);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ServerConfig {
+    /// IP time-to-live of accepted connections.
+    pub ttl: u32,
+    /// Whether `TCP_NODELAY` is set on accepted connections.
+    pub nodelay: bool,
+    /// `SO_LINGER` of accepted connections, `None` keeps the system default.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub linger: Option<Duration>,
+    /// Whether `SO_KEEPALIVE` is set on accepted connections.
+    pub keepalive: bool,
+    /// Deadline for the whole handshake of a connection.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub handshake_timeout: Option<Duration>,
+    /// Longest time a single read may wait for the peer.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub idle_timeout: Option<Duration>,
+    /// Largest encrypted message accepted from a peer, in bytes.
+    pub max_message_size: usize,
+    /// Largest number of connections handled at the same time.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub max_connections: Option<usize>,
+    /// Largest number of connections handled at the same time for a single peer IP.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub max_connections_per_ip: Option<usize>,
+    /// Length of the queue of pending connections passed to `listen`.
+    pub backlog: u32,
+    /// Whether sessions stay open after a response, waiting for further requests.
+    pub persistent: bool,
+    /// Largest number of requests served on a persistent session before it is closed.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub max_requests: Option<usize>,
+    /// Longest time a persistent session waits for the next request.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub persistent_timeout: Option<Duration>,
+    /// Peer IP ranges allowed or denied to connect, checked before the handshake.
+    pub access: AccessList,
+    /// Rate of requests allowed per peer IP across all routes, unlimited by default.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub rate_limit: Option<RateLimit>,
+    /// Access log written for every handled request, disabled by default.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub access_log: Option<AccessLogConfig>,
+    /// Compression offered to clients accepting it, disabled by default.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub compression: Option<CompressionConfig>,
+    /// Pings sent to clients to detect the ones that vanished, disabled by default.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub heartbeat: Option<HeartbeatConfig>,
+    /// Path WebSocket upgrades are accepted on, connections speak raw TCP when unset.
+    #[cfg(feature = "websocket")]
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub websocket: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            ttl: 20,
+            nodelay: true,
+            linger: Some(Duration::ZERO),
+            keepalive: true,
+            handshake_timeout: Some(Duration::from_secs(10)),
+            idle_timeout: None,
+            max_message_size: 16 * 1024 * 1024,
+            max_connections: None,
+            max_connections_per_ip: None,
+            backlog: 1024,
+            persistent: false,
+            max_requests: None,
+            persistent_timeout: Some(Duration::from_secs(60)),
+            access: AccessList::default(),
+            rate_limit: None,
+            access_log: None,
+            compression: None,
+            heartbeat: None,
+            #[cfg(feature = "websocket")]
+            websocket: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load the configuration from `OBLIVION_*` environment variables.
+    ///
+    /// Every unset variable falls back to the default value, durations are given
+    /// in seconds and `none` disables an optional setting:
+    ///
+    /// - `OBLIVION_TTL`
+    /// - `OBLIVION_NODELAY`
+    /// - `OBLIVION_LINGER`
+    /// - `OBLIVION_KEEPALIVE`
+    /// - `OBLIVION_HANDSHAKE_TIMEOUT`
+    /// - `OBLIVION_IDLE_TIMEOUT`
+    /// - `OBLIVION_MAX_MESSAGE_SIZE`
+    /// - `OBLIVION_MAX_CONNECTIONS`
+    /// - `OBLIVION_MAX_CONNECTIONS_PER_IP`
+    /// - `OBLIVION_BACKLOG`
+    /// - `OBLIVION_PERSISTENT`
+    /// - `OBLIVION_MAX_REQUESTS`
+    /// - `OBLIVION_PERSISTENT_TIMEOUT`
+    /// - `OBLIVION_ALLOW`, comma separated CIDR ranges
+    /// - `OBLIVION_DENY`, comma separated CIDR ranges
+    /// - `OBLIVION_ALLOW_LOCAL`, permit peers without an IP address despite the ranges
+    /// - `OBLIVION_RATE_LIMIT_BURST`, requests a peer IP may send at once
+    /// - `OBLIVION_RATE_LIMIT_PER_SECOND`, only along with `OBLIVION_RATE_LIMIT_BURST`
+    /// - `OBLIVION_ACCESS_LOG`, path of the access log
+    /// - `OBLIVION_ACCESS_LOG_FORMAT`, `common`, `json` or a template
+    /// - `OBLIVION_COMPRESSION`, comma separated encodings by preference
+    /// - `OBLIVION_HEARTBEAT_INTERVAL`
+    /// - `OBLIVION_HEARTBEAT_MAX_MISSED`, only along with `OBLIVION_HEARTBEAT_INTERVAL`
+    /// - `OBLIVION_WEBSOCKET`, path WebSocket upgrades are accepted on
+    pub fn from_env() -> Result<Self, Exception> {
+        let mut config = Self::default();
+        if let Some(ttl) = env_value("TTL")? {
+            config.ttl = ttl;
+        }
+        if let Some(nodelay) = env_value("NODELAY")? {
+            config.nodelay = nodelay;
+        }
+        if let Some(linger) = env_duration("LINGER")? {
+            config.linger = linger;
+        }
+        if let Some(keepalive) = env_value("KEEPALIVE")? {
+            config.keepalive = keepalive;
+        }
+        if let Some(timeout) = env_duration("HANDSHAKE_TIMEOUT")? {
+            config.handshake_timeout = timeout;
+        }
+        if let Some(timeout) = env_duration("IDLE_TIMEOUT")? {
+            config.idle_timeout = timeout;
+        }
+        if let Some(size) = env_value("MAX_MESSAGE_SIZE")? {
+            config.max_message_size = size;
+        }
+        if let Some(connections) = env_optional("MAX_CONNECTIONS")? {
+            config.max_connections = connections;
+        }
+        if let Some(connections) = env_optional("MAX_CONNECTIONS_PER_IP")? {
+            config.max_connections_per_ip = connections;
+        }
+        if let Some(backlog) = env_value("BACKLOG")? {
+            config.backlog = backlog;
+        }
+        if let Some(persistent) = env_value("PERSISTENT")? {
+            config.persistent = persistent;
+        }
+        if let Some(requests) = env_optional("MAX_REQUESTS")? {
+            config.max_requests = requests;
+        }
+        if let Some(timeout) = env_duration("PERSISTENT_TIMEOUT")? {
+            config.persistent_timeout = timeout;
+        }
+        if let Some(allow) = env_cidrs("ALLOW")? {
+            config.access.allow = allow;
+        }
+        if let Some(deny) = env_cidrs("DENY")? {
+            config.access.deny = deny;
+        }
+        if let Some(allow_local) = env_value("ALLOW_LOCAL")? {
+            config.access.allow_local = allow_local;
+        }
+        if let Some(burst) = env_optional("RATE_LIMIT_BURST")? {
+            config.rate_limit = burst.map(|burst| RateLimit::new(burst, 1.0));
+        }
+        if let Some(per_second) = env_value("RATE_LIMIT_PER_SECOND")? {
+            let Some(rate_limit) = &mut config.rate_limit else {
+                return Err(Exception::InvalidConfig(format!(
+                    "{}RATE_LIMIT_PER_SECOND requires {}RATE_LIMIT_BURST",
+                    ENV_PREFIX, ENV_PREFIX
+                )));
+            };
+            rate_limit.per_second = per_second;
+        }
+        if let Some(path) = env_optional::<String>("ACCESS_LOG")? {
+            config.access_log =
+                path.map(|path| AccessLogConfig::new(path.trim(), AccessLogFormat::Common));
+        }
+        if let Some(format) = env_value("ACCESS_LOG_FORMAT")? {
+            if let Some(access_log) = &mut config.access_log {
+                access_log.format = format;
+            }
+        }
+        if let Some(encodings) = env_optional::<String>("COMPRESSION")? {
+            config.compression = encodings
+                .map(|encodings| {
+                    encodings
+                        .split(',')
+                        .filter(|encoding| !encoding.trim().is_empty())
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                })
+                .transpose()?
+                .map(CompressionConfig::new);
+        }
+        if let Some(interval) = env_duration("HEARTBEAT_INTERVAL")? {
+            config.heartbeat = interval.map(HeartbeatConfig::new);
+        }
+        if let Some(missed) = env_value("HEARTBEAT_MAX_MISSED")? {
+            let Some(heartbeat) = &mut config.heartbeat else {
+                return Err(Exception::InvalidConfig(format!(
+                    "{}HEARTBEAT_MAX_MISSED requires {}HEARTBEAT_INTERVAL",
+                    ENV_PREFIX, ENV_PREFIX
+                )));
+            };
+            heartbeat.max_missed = missed;
+        }
+        if let Some(heartbeat) = &config.heartbeat {
+            heartbeat.validate()?;
+        }
+        #[cfg(feature = "websocket")]
+        if let Some(path) = env_optional("WEBSOCKET")? {
+            config.websocket = path;
+        }
+        Ok(config)
+    }
+
+    /// Load the configuration from a TOML document.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use oblivion::models::config::ServerConfig;
+    /// use oblivion::models::limiter::RateLimit;
+    ///
+    /// let config = ServerConfig::from_toml(
+    ///     r#"
+    ///     handshake_timeout = 2.5
+    ///     max_connections = 1024
+    ///
+    ///     [rate_limit]
+    ///     burst = 20
+    ///     per_second = 5.0
+    ///     "#,
+    /// )
+    /// .unwrap();
+    ///
+    /// assert_eq!(config.handshake_timeout, Some(Duration::from_millis(2500)));
+    /// assert_eq!(config.max_connections, Some(1024));
+    /// assert_eq!(config.rate_limit, Some(RateLimit::new(20, 5.0)));
+    /// assert_eq!(config.ttl, 20);
+    ///
+    /// // Durations disabled with `"none"` stay disabled across a round trip.
+    /// let config = ServerConfig {
+    ///     linger: None,
+    ///     handshake_timeout: None,
+    ///     ..ServerConfig::default()
+    /// };
+    /// let document = toml::to_string(&config).unwrap();
+    /// assert!(document.contains(r#"handshake_timeout = "none""#));
+    /// assert_eq!(ServerConfig::from_toml(&document).unwrap(), config);
+    /// ```
+    #[cfg(feature = "toml")]
+    pub fn from_toml(document: &str) -> Result<Self, Exception> {
+        let config: Self = toml::from_str(document)
+            .map_err(|error| Exception::InvalidConfig(error.to_string()))?;
+        if let Some(heartbeat) = &config.heartbeat {
+            heartbeat.validate()?;
+        }
+        Ok(config)
+    }
+}
+
+/// Client Retry Policy
+///
+/// Failed connection attempts are retried up to `max_retries` times, waiting
+/// `initial_backoff` before the first retry and multiplying the wait by `multiplier`
+/// after each one, up to `max_backoff`. A request with a body is only retried if
+/// it failed before the body was sent, unless `retry_sent` is set.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use oblivion::models::config::RetryPolicy;
+///
+/// let policy = RetryPolicy::new(3);
+///
+/// assert_eq!(policy.backoff(0), Duration::from_millis(100));
+/// assert_eq!(policy.backoff(2), Duration::from_millis(400));
+/// assert!(!policy.retry_sent);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub initial_backoff: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Whether requests with a body are also retried once the body was sent, which
+    /// the server may then handle twice. Only enable it for idempotent requests.
+    pub retry_sent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            retry_sent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Retry up to `max_retries` times with the default backoff.
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Self::default()
+        }
+    }
+
+    /// Wait before the retry following the failed attempt number `attempt`, counted from zero.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        Duration::try_from_secs_f64(backoff)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// Oblivion Client Configuration
+///
+/// The default value matches the historical behavior of `Client::connect`.
+///
+/// ```rust
+/// use oblivion::models::config::ClientConfig;
+///
+/// let config = ClientConfig::default();
+///
+/// assert_eq!(config.ttl, 20);
+/// assert_eq!(config.connect_timeout, None);
+/// assert_eq!(config.retry.max_retries, 0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ClientConfig {
+    /// IP time-to-live of the connection.
+    pub ttl: u32,
+    /// Whether `TCP_NODELAY` is set.
+    pub nodelay: bool,
+    /// Whether `SO_KEEPALIVE` is set.
+    pub keepalive: bool,
+    /// Idle time before keepalive probes are sent, `None` keeps the system default.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub keepalive_time: Option<Duration>,
+    /// Deadline for resolving the host and establishing the stream, proxy included.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub connect_timeout: Option<Duration>,
+    /// Deadline for the Oblivion handshake once the stream is established.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub handshake_timeout: Option<Duration>,
+    /// Longest time a single read may wait for the server.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub read_timeout: Option<Duration>,
+    /// Local address the connection is bound to before connecting.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub local_address: Option<SocketAddr>,
+    pub retry: RetryPolicy,
+    /// Compression accepted from the server, disabled by default.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub compression: Option<CompressionConfig>,
+    /// Pings sent to the server to detect it vanished, disabled by default.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub heartbeat: Option<HeartbeatConfig>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            ttl: 20,
+            nodelay: true,
+            keepalive: true,
+            keepalive_time: None,
+            connect_timeout: None,
+            handshake_timeout: None,
+            read_timeout: None,
+            local_address: None,
+            retry: RetryPolicy::default(),
+            compression: None,
+            heartbeat: None,
+        }
+    }
+}
+
+/// Oblivion Compression Configuration
+///
+/// Messages are compressed with the first of `encodings` the peer accepts once they
+/// reach `min_size` bytes, see [`compression`](super::compression) for the risks of
+/// compressing secrets.
+///
+/// ```rust
+/// use oblivion::models::compression::Encoding;
+/// use oblivion::models::config::CompressionConfig;
+///
+/// let config = CompressionConfig::new(vec![Encoding::Zstd]);
+///
+/// assert_eq!(config.min_size, 1024);
+/// assert_eq!(config.max_decompressed_size, 16 * 1024 * 1024);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CompressionConfig {
+    /// Encodings by preference, the ones not compiled in are ignored.
+    pub encodings: Vec<Encoding>,
+    /// Smallest message compressed, in bytes.
+    pub min_size: usize,
+    /// Largest message accepted from the peer once decompressed, in bytes.
+    pub max_decompressed_size: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            encodings: Encoding::supported(),
+            min_size: 1024,
+            max_decompressed_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Compress with `encodings` by preference and the default limits.
+    pub fn new(encodings: Vec<Encoding>) -> Self {
+        Self {
+            encodings,
+            ..Self::default()
+        }
+    }
+}
+
+/// Oblivion Heartbeat Configuration
+///
+/// A ping is sent every `interval` and the session is closed once `max_missed` of
+/// them in a row are left unanswered. Pongs are only noticed while the session is
+/// being read, see [`heartbeat`](super::heartbeat).
+///
+/// ```rust
+/// use std::time::Duration;
+/// use oblivion::models::config::HeartbeatConfig;
+///
+/// let config = HeartbeatConfig::new(Duration::from_secs(10));
+///
+/// assert_eq!(config.max_missed, 3);
+/// assert_eq!(HeartbeatConfig::default().interval, Duration::from_secs(30));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct HeartbeatConfig {
+    /// Time between two pings.
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub interval: Duration,
+    /// Unanswered pings in a row after which the peer is given up on.
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            max_missed: 3,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// Ping every `interval`, giving up after the default number of missed pongs.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            ..Self::default()
+        }
+    }
+
+    /// Fail with `Exception::InvalidConfig` unless both the interval and `max_missed`
+    /// are above zero.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use oblivion::models::config::HeartbeatConfig;
+    ///
+    /// assert!(HeartbeatConfig::default().validate().is_ok());
+    /// assert!(HeartbeatConfig::new(Duration::ZERO).validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), Exception> {
+        if self.interval.is_zero() {
+            return Err(Exception::InvalidConfig(
+                "heartbeat interval must be above zero".to_string(),
+            ));
+        }
+        if self.max_missed == 0 {
+            return Err(Exception::InvalidConfig(
+                "heartbeat max_missed must be above zero".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Oblivion Connection Pool Configuration
+///
+/// ```rust
+/// use std::time::Duration;
+/// use oblivion::models::config::PoolConfig;
+///
+/// let config = PoolConfig::default();
+///
+/// assert_eq!(config.max_per_host, 8);
+/// assert_eq!(config.idle_timeout, Some(Duration::from_secs(90)));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PoolConfig {
+    /// Largest number of sessions open to a single host, further requests wait for one to be free.
+    pub max_per_host: usize,
+    /// Idle sessions are closed instead of reused after this long, `None` keeps them open.
+    #[cfg_attr(feature = "serde", serde(with = "duration_secs"))]
+    pub idle_timeout: Option<Duration>,
+    /// Whether idle sessions are checked for a connection closed by the server before reuse.
+    pub health_check: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_per_host: 8,
+            idle_timeout: Some(Duration::from_secs(90)),
+            health_check: true,
+        }
+    }
+}
+
+fn env_value<T: FromStr>(key: &str) -> Result<Option<T>, Exception> {
+    match env::var(format!("{}{}", ENV_PREFIX, key)) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| Exception::InvalidConfig(format!("{}{}={}", ENV_PREFIX, key, value))),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_optional<T: FromStr>(key: &str) -> Result<Option<Option<T>>, Exception> {
+    match env::var(format!("{}{}", ENV_PREFIX, key)) {
+        Ok(value) if value.trim().eq_ignore_ascii_case("none") => Ok(Some(None)),
+        Ok(_) => Ok(env_value(key)?.map(Some)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_cidrs(key: &str) -> Result<Option<Vec<Cidr>>, Exception> {
+    match env::var(format!("{}{}", ENV_PREFIX, key)) {
+        Ok(value) => value
+            .split(',')
+            .filter(|cidr| !cidr.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_duration(key: &str) -> Result<Option<Option<Duration>>, Exception> {
+    match env_optional::<f64>(key)? {
+        Some(Some(secs)) => Duration::try_from_secs_f64(secs)
+            .map(|duration| Some(Some(duration)))
+            .map_err(|_| Exception::InvalidConfig(format!("{}{}={}", ENV_PREFIX, key, secs))),
+        Some(None) => Ok(Some(None)),
+        None => Ok(None),
+    }
+}
+
+/// Optional durations in seconds, `"none"` standing for `None` so that it survives
+/// a round trip even where the default is `Some`.
+#[cfg(feature = "serde")]
+mod duration_secs {
+    use std::fmt;
+    use std::time::Duration;
+
+    use serde::de::{Error, Visitor};
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_f64(duration.as_secs_f64()),
+            None => serializer.serialize_str("none"),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        deserializer.deserialize_any(DurationVisitor)
+    }
+
+    struct DurationVisitor;
+
+    impl<'de> Visitor<'de> for DurationVisitor {
+        type Value = Option<Duration>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a number of seconds or \"none\"")
+        }
+
+        fn visit_f64<E: Error>(self, secs: f64) -> Result<Self::Value, E> {
+            Duration::try_from_secs_f64(secs)
+                .map(Some)
+                .map_err(E::custom)
+        }
+
+        fn visit_i64<E: Error>(self, secs: i64) -> Result<Self::Value, E> {
+            self.visit_f64(secs as f64)
+        }
+
+        fn visit_u64<E: Error>(self, secs: u64) -> Result<Self::Value, E> {
+            self.visit_f64(secs as f64)
+        }
+
+        fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+            match value.trim() {
+                value if value.eq_ignore_ascii_case("none") => Ok(None),
+                _ => Err(E::invalid_value(serde::de::Unexpected::Str(value), &self)),
+            }
+        }
+
+        fn visit_none<E: Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(
+            self,
+            deserializer: D,
+        ) -> Result<Self::Value, D::Error> {
+            deserializer.deserialize_any(self)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod secs {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Duration::try_from_secs_f64(f64::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
@@ -0,0 +1,292 @@
+//! # Oblivion File Responses
+//!
+//! Streams files from disk as bodies, see [`super::body`], so that files of any
+//! size are served with bounded memory. The body is preceded by a message with
+//! flag `7` carrying the metadata of the file as JSON.
+use std::ops::{Bound, Range, RangeBounds};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, SeekFrom};
+
+use crate::exceptions::Exception;
+
+use super::session::Session;
+
+/// Flag of the message announcing a file and its metadata.
+pub(crate) const FILE_FLAG: u32 = 7;
+
+/// Oblivion File Response
+///
+/// Serves the file at a trusted path with [`FileResponse::new`], or a path taken
+/// from the entrance with [`FileResponse::within`], which refuses to leave its root.
+///
+/// ```rust
+/// use oblivion::models::file::FileResponse;
+/// use oblivion::models::render::BaseResponse;
+/// use oblivion::models::router::{RoutePath, RouteType, Router};
+/// use oblivion::models::server::Server;
+/// use oblivion::models::session::Session;
+/// use oblivion::models::client::Client;
+/// use oblivion::types::ServerResponse;
+/// use oblivion_codegen::async_route;
+/// use tokio::net::TcpListener;
+///
+/// #[async_route]
+/// fn download(mut session: Session) -> ServerResponse {
+///     let name = session.request.get_entrance().trim_start_matches("/files/").to_string();
+///     let root = std::env::temp_dir().join("oblivion-files");
+///     Ok(BaseResponse::FileResponse(FileResponse::within(root, name).range(6..)))
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let root = std::env::temp_dir().join("oblivion-files");
+/// std::fs::create_dir_all(&root)?;
+/// std::fs::write(root.join("hello.txt"), "Hello Oblivion!")?;
+///
+/// let mut router = Router::new();
+/// router.route(RoutePath::new("/files/", RouteType::StartswithPath), download);
+/// let listener = TcpListener::bind("127.0.0.1:0").await?;
+/// let server = Server::builder(router).listener(listener).build();
+/// let address = server.local_addr().unwrap();
+/// tokio::spawn(async move { server.run().await });
+///
+/// let client = Client::connect(&format!("{}/files/hello.txt", address)).await?;
+/// let mut content = Vec::new();
+/// let meta = client.recv_file(&mut content).await?;
+/// assert_eq!(content, b"Oblivion!");
+/// assert_eq!(meta.name, "hello.txt");
+/// assert_eq!(meta.content_type, "text/plain");
+/// assert_eq!((meta.size, meta.range), (15, 6..15));
+///
+/// let client = Client::connect(&format!("{}/files/../secret", address)).await?;
+/// assert!(client.recv_file(&mut Vec::new()).await.is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileResponse {
+    root: Option<PathBuf>,
+    path: PathBuf,
+    start: u64,
+    end: Option<u64>,
+    name: Option<String>,
+    content_type: Option<String>,
+}
+
+/// Metadata of a file, sent ahead of its content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMeta {
+    pub name: String,
+    /// Size of the whole file in bytes.
+    pub size: u64,
+    pub content_type: String,
+    pub modified: Option<DateTime<Utc>>,
+    /// Bytes of the file sent in the body.
+    pub range: Range<u64>,
+}
+
+impl FileResponse {
+    /// Serve the file at `path`, which must not come from the peer.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            root: None,
+            path: path.into(),
+            start: 0,
+            end: None,
+            name: None,
+            content_type: None,
+        }
+    }
+
+    /// Serve the file at `path` relative to `root`.
+    ///
+    /// Paths escaping `root`, through `..`, an absolute path or a symbolic link,
+    /// are refused with `Exception::PathTraversal` when the file is opened.
+    pub fn within(root: impl Into<PathBuf>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            root: Some(root.into()),
+            ..Self::new(path)
+        }
+    }
+
+    /// Only send the bytes of the file in `range`, such as `offset..` to resume a download.
+    pub fn range(mut self, range: impl RangeBounds<u64>) -> Self {
+        self.start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        self.end = match range.end_bound() {
+            Bound::Included(end) => Some(end.saturating_add(1)),
+            Bound::Excluded(end) => Some(*end),
+            Bound::Unbounded => None,
+        };
+        self
+    }
+
+    /// Name sent to the peer instead of the name of the file.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Content type sent to the peer instead of the one guessed from the extension.
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    /// Resolve the path of the file, checking that it stays within the root.
+    pub async fn resolve(&self) -> Result<PathBuf> {
+        let Some(root) = &self.root else {
+            return Ok(self.path.clone());
+        };
+        let traversal = || Exception::PathTraversal {
+            path: self.path.display().to_string(),
+        };
+        if !self
+            .path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(traversal().into());
+        }
+        let root = tokio::fs::canonicalize(root).await?;
+        let path = tokio::fs::canonicalize(root.join(&self.path)).await?;
+        if !path.starts_with(&root) {
+            return Err(traversal().into());
+        }
+        Ok(path)
+    }
+
+    /// Open the file, seeked to the start of the range, along with its metadata.
+    pub async fn open(&self) -> Result<(File, FileMeta)> {
+        let path = self.resolve().await?;
+        let mut file = File::open(&path).await?;
+        let metadata = file.metadata().await?;
+        if !metadata.is_file() {
+            return Err(std::io::Error::from(std::io::ErrorKind::NotFound).into());
+        }
+
+        let size = metadata.len();
+        let end = self.end.map_or(size, |end| end.min(size));
+        if self.start > size || self.start > end {
+            return Err(Exception::RangeNotSatisfiable {
+                start: self.start,
+                end: self.end,
+                size,
+            }
+            .into());
+        }
+        file.seek(SeekFrom::Start(self.start)).await?;
+
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => file_name(&path),
+        };
+        let content_type = match &self.content_type {
+            Some(content_type) => content_type.clone(),
+            None => mime_guess::from_path(&name)
+                .first_or_octet_stream()
+                .to_string(),
+        };
+        let meta = FileMeta {
+            name,
+            size,
+            content_type,
+            modified: metadata.modified().ok().map(DateTime::from),
+            range: self.start..end,
+        };
+        Ok((file, meta))
+    }
+}
+
+impl FileMeta {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "size": self.size,
+            "content_type": self.content_type,
+            "modified": self.modified.map(|modified| modified.to_rfc3339()),
+            "start": self.range.start,
+            "end": self.range.end,
+        })
+    }
+
+    pub fn from_json(json: &Value) -> Result<Self, Exception> {
+        let invalid = || Exception::InvalidHeader(format!("invalid file metadata: {}", json));
+        let string = |key: &str| json[key].as_str().map(str::to_string).ok_or_else(invalid);
+        let number = |key: &str| json[key].as_u64().ok_or_else(invalid);
+        let modified = match &json["modified"] {
+            Value::Null => None,
+            modified => Some(
+                modified
+                    .as_str()
+                    .and_then(|modified| DateTime::parse_from_rfc3339(modified).ok())
+                    .ok_or_else(invalid)?
+                    .to_utc(),
+            ),
+        };
+        Ok(Self {
+            name: string("name")?,
+            size: number("size")?,
+            content_type: string("content_type")?,
+            modified,
+            range: number("start")?..number("end")?,
+        })
+    }
+}
+
+impl From<FileResponse> for super::render::BaseResponse {
+    fn from(file: FileResponse) -> Self {
+        Self::FileResponse(file)
+    }
+}
+
+impl Session {
+    /// Send `file` to the peer, its metadata first and then its content as a body.
+    pub async fn send_file(&self, file: &FileResponse) -> Result<FileMeta> {
+        let (file, meta) = file.open().await?;
+        self.send_opened(file, &meta).await?;
+        Ok(meta)
+    }
+
+    /// Send a file already opened by [`FileResponse::open`].
+    pub(crate) async fn send_opened(&self, file: File, meta: &FileMeta) -> Result<()> {
+        self.send_frame(FILE_FLAG, meta.to_json().to_string().into_bytes())
+            .await?;
+        let mut body = self.send_body();
+        body.copy_from(&mut file.take(meta.range.end - meta.range.start))
+            .await?;
+        body.finish().await
+    }
+
+    /// Receive a file sent by the peer, writing its content to `writer`.
+    ///
+    /// Messages other than a file fail with `Exception::InvalidHeader`. A handler
+    /// returning a file still ends with a final response, which has no content.
+    pub async fn recv_file<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<FileMeta> {
+        let response = self.recv().await?;
+        if response.flag != FILE_FLAG {
+            return Err(Exception::InvalidHeader(format!(
+                "expected a file, received frame {}",
+                response.flag
+            ))
+            .into());
+        }
+        let meta = FileMeta::from_json(&serde_json::from_slice(&response.content)?)?;
+        self.recv_body().copy_to(writer).await?;
+        Ok(meta)
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
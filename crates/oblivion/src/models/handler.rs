@@ -14,5 +14,6 @@ pub fn not_found(session: Session) -> ServerResponse {
     Ok(BaseResponse::TextResponse(format!(
         "Path {} is not found, error with code 404.",
         entrance
-    )))
+    ))
+    .with_status(404))
 }
@@ -0,0 +1,132 @@
+//! # Oblivion Heartbeat
+//!
+//! Detects peers that vanished without closing the connection. A session with a
+//! heartbeat sends a ping with flag `10` carrying a sequence number on every interval,
+//! which the peer answers with a pong with flag `11` echoing it. Both are handled
+//! while reading the session and never reach the caller.
+//!
+//! Pongs are only noticed while the session is being read, e.g. by `Session::listen`
+//! or a multiplexer, so pings are only sent while a read is in progress and only
+//! count as missed when it lasted the whole interval. Any message received proves
+//! the peer alive. Persistent servers do not ping sessions awaiting their next
+//! request, and pooled sessions handle the pings and pongs received while idle when
+//! checked out. Once the peer leaves `max_missed` pings in a row unanswered, the
+//! session is closed and its pending reads fail with `Exception::HeartbeatTimeout`.
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+/// Flag of a ping, answered with a pong carrying the same content.
+pub(crate) const PING_FLAG: u32 = 10;
+
+/// Flag of a pong answering a ping.
+pub(crate) const PONG_FLAG: u32 = 11;
+
+/// Heartbeat state of a session, shared by the sessions of its requests.
+pub(crate) struct Heartbeat {
+    sequence: AtomicU64,
+    /// Pings sent since the last message received.
+    unanswered: AtomicU32,
+    /// Reads in progress.
+    readers: AtomicU32,
+    /// Whether the session went unread since the last tick.
+    interrupted: AtomicBool,
+    /// Sequence number and send time of the last ping.
+    last_ping: Mutex<Option<(u64, Instant)>>,
+    rtt: Mutex<Option<Duration>>,
+    expired: watch::Sender<bool>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new() -> Self {
+        Self {
+            sequence: AtomicU64::new(0),
+            unanswered: AtomicU32::new(0),
+            readers: AtomicU32::new(0),
+            interrupted: AtomicBool::new(true),
+            last_ping: Mutex::new(None),
+            rtt: Mutex::new(None),
+            expired: watch::Sender::new(false),
+        }
+    }
+
+    /// Content of the next ping.
+    pub(crate) fn ping(&self) -> Vec<u8> {
+        let sequence = self.sequence.fetch_add(1, Ordering::AcqRel) + 1;
+        *self.last_ping.lock().unwrap() = Some((sequence, Instant::now()));
+        self.unanswered.fetch_add(1, Ordering::AcqRel);
+        sequence.to_be_bytes().to_vec()
+    }
+
+    /// Record a message from the peer, which answers every ping sent so far.
+    #[inline]
+    pub(crate) fn alive(&self) {
+        self.unanswered.store(0, Ordering::Release);
+    }
+
+    /// Record a pong with `content`, measuring the round trip if it answers the last ping.
+    pub(crate) fn pong(&self, content: &[u8]) {
+        self.alive();
+        let Ok(sequence) = content.try_into().map(u64::from_be_bytes) else {
+            return;
+        };
+        let mut last_ping = self.last_ping.lock().unwrap();
+        if let Some((_, sent)) = last_ping.filter(|(last, _)| *last == sequence) {
+            *self.rtt.lock().unwrap() = Some(sent.elapsed());
+            *last_ping = None;
+        }
+    }
+
+    #[inline]
+    pub(crate) fn unanswered(&self) -> u32 {
+        self.unanswered.load(Ordering::Acquire)
+    }
+
+    /// Mark a read in progress until the guard is dropped.
+    pub(crate) fn reading(&self) -> Reading<'_> {
+        self.readers.fetch_add(1, Ordering::AcqRel);
+        Reading(self)
+    }
+
+    /// Whether a read is in progress, noticing a pong as soon as it arrives.
+    #[inline]
+    pub(crate) fn is_read(&self) -> bool {
+        self.readers.load(Ordering::Acquire) > 0
+    }
+
+    /// Whether the session was read during the whole interval since the last tick,
+    /// so that a pong would have been noticed.
+    pub(crate) fn watched(&self) -> bool {
+        let reading = self.is_read();
+        let interrupted = self.interrupted.swap(!reading, Ordering::AcqRel);
+        reading && !interrupted
+    }
+
+    #[inline]
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        *self.rtt.lock().unwrap()
+    }
+
+    /// Give up on the peer, waking up pending reads.
+    pub(crate) fn expire(&self) {
+        self.expired.send_replace(true);
+    }
+
+    /// Wait until the peer is given up on.
+    pub(crate) async fn expired(&self) {
+        let _ = self.expired.subscribe().wait_for(|expired| *expired).await;
+    }
+}
+
+/// Read in progress, see [`Heartbeat::reading`].
+pub(crate) struct Reading<'a>(&'a Heartbeat);
+
+impl Drop for Reading<'_> {
+    fn drop(&mut self) {
+        if self.0.readers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.interrupted.store(true, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! # Oblivion Rate Limiter
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::utils::parser::OblivionRequest;
+
+use super::acl::Cidr;
+
+/// Buckets kept at most, past it new keys share a single overflow bucket until
+/// buckets of other keys are refilled.
+const MAX_BUCKETS: usize = 10_000;
+
+/// Function deriving the rate limiting key of a request.
+pub type RateLimitKey = fn(&OblivionRequest) -> String;
+
+/// Key requests by the IP address of the peer, IPv6 peers by their `/64` network
+/// since a single host usually owns all of it.
+///
+/// ```rust
+/// use oblivion::models::limiter::by_ip;
+/// use oblivion::utils::parser::OblivionRequest;
+///
+/// let mut request = OblivionRequest::new("CONNECT /limited Oblivion/2.0")?;
+/// request.set_remote_peer(&"[2001:db8::1:2:3:4]:7076".parse()?);
+/// assert_eq!(by_ip(&request), "2001:db8::/64");
+///
+/// request.set_remote_peer(&"[::ffff:10.0.0.1]:7076".parse()?);
+/// assert_eq!(by_ip(&request), "10.0.0.1");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub fn by_ip(request: &OblivionRequest) -> String {
+    match request.get_ip().parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+            Some(ip) => ip.to_string(),
+            None => Cidr::new(IpAddr::V6(ip), 64)
+                .map_or_else(|_| ip.to_string(), |network| network.to_string()),
+        },
+        _ => request.get_ip().to_string(),
+    }
+}
+
+/// Token Bucket Rate
+///
+/// A bucket holds at most `burst` tokens and regains `per_second` tokens every second,
+/// each request consumes one token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        Self { burst, per_second }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    keys: HashMap<String, Bucket>,
+    /// Bucket shared by the keys arriving while `keys` is full.
+    overflow: Bucket,
+    /// Earliest time a bucket of `keys` may be refilled and dropped, `None` if never.
+    next_refill: Option<Instant>,
+}
+
+/// Oblivion Rate Limiter
+///
+/// Token bucket rate limiter keeping one bucket per key, by default the peer IP.
+/// Only refilled buckets are dropped, a throttled key stays throttled however many
+/// other keys show up.
+///
+/// ```rust
+/// use oblivion::models::limiter::{RateLimit, RateLimiter};
+///
+/// let limiter = RateLimiter::new(RateLimit::new(2, 0.5));
+///
+/// assert!(limiter.check_key("127.0.0.1"));
+/// assert!(limiter.check_key("127.0.0.1"));
+/// assert!(!limiter.check_key("127.0.0.1"));
+/// assert!(limiter.check_key("::1"));
+/// ```
+pub struct RateLimiter {
+    limit: RateLimit,
+    key: RateLimitKey,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self::with_key(limit, by_ip)
+    }
+
+    /// Create a rate limiter keying requests with `key`, e.g. by a header of the request.
+    ///
+    /// Requests are checked before they reach their handler, so identities set
+    /// with `Session::set_identity` are not known yet.
+    pub fn with_key(limit: RateLimit, key: RateLimitKey) -> Self {
+        Self {
+            limit,
+            key,
+            buckets: Mutex::new(Buckets {
+                keys: HashMap::new(),
+                overflow: Bucket {
+                    tokens: limit.burst as f64,
+                    updated: Instant::now(),
+                },
+                next_refill: None,
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Consume a token for `request`, returns `false` if the request should be throttled.
+    pub fn check(&self, request: &OblivionRequest) -> bool {
+        self.check_key(&(self.key)(request))
+    }
+
+    /// Consume a token from the bucket of `key`, returns `false` if it is exhausted.
+    ///
+    /// ```rust
+    /// use oblivion::models::limiter::{RateLimit, RateLimiter};
+    ///
+    /// let limiter = RateLimiter::new(RateLimit::new(1, 0.001));
+    /// assert!(limiter.check_key("attacker"));
+    /// assert!(!limiter.check_key("attacker"));
+    ///
+    /// // Flooding the limiter with new keys does not hand out a fresh burst.
+    /// for index in 0..20_000 {
+    ///     limiter.check_key(&index.to_string());
+    /// }
+    /// assert!(!limiter.check_key("attacker"));
+    /// assert!(!limiter.check_key("newcomer"));
+    /// ```
+    pub fn check_key(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let burst = self.limit.burst as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = &mut *buckets;
+
+        if buckets.keys.len() >= MAX_BUCKETS && !buckets.keys.contains_key(key) {
+            self.evict(buckets, now);
+        }
+
+        let bucket = if buckets.keys.len() < MAX_BUCKETS || buckets.keys.contains_key(key) {
+            buckets.keys.entry(key.to_string()).or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+            })
+        } else {
+            &mut buckets.overflow
+        };
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.limit.per_second).min(burst);
+        bucket.updated = now;
+
+        let permitted = bucket.tokens >= 1.0;
+        if permitted {
+            bucket.tokens -= 1.0;
+        }
+        if let Some(refilled) = self.refilled_at(bucket) {
+            buckets.next_refill = Some(
+                buckets
+                    .next_refill
+                    .map_or(refilled, |next| next.min(refilled)),
+            );
+        }
+        permitted
+    }
+
+    /// Give back the token consumed for `request` once it is throttled elsewhere.
+    pub(crate) fn refund(&self, request: &OblivionRequest) {
+        let burst = self.limit.burst as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = &mut *buckets;
+        let bucket = match buckets.keys.get_mut(&(self.key)(request)) {
+            Some(bucket) => bucket,
+            None => &mut buckets.overflow,
+        };
+        bucket.tokens = (bucket.tokens + 1.0).min(burst);
+    }
+
+    /// Drop the buckets refilled by now, which are no different from new ones, and
+    /// remember when the next remaining one will be so that a full map of throttled
+    /// keys is not scanned again for every new key.
+    fn evict(&self, buckets: &mut Buckets, now: Instant) {
+        if buckets.next_refill.is_none_or(|refill| now < refill) {
+            return;
+        }
+        let mut next_refill = None::<Instant>;
+        buckets
+            .keys
+            .retain(|_, bucket| match self.refilled_at(bucket) {
+                Some(refilled) if refilled <= now => false,
+                Some(refilled) => {
+                    next_refill = Some(next_refill.map_or(refilled, |next| next.min(refilled)));
+                    true
+                }
+                None => true,
+            });
+        buckets.next_refill = next_refill;
+    }
+
+    /// Time `bucket` holds `burst` tokens again, `None` if it never will.
+    fn refilled_at(&self, bucket: &Bucket) -> Option<Instant> {
+        let missing = self.limit.burst as f64 - bucket.tokens;
+        if missing <= 0.0 {
+            return Some(bucket.updated);
+        }
+        Duration::try_from_secs_f64(missing / self.limit.per_second)
+            .ok()
+            .and_then(|refill| bucket.updated.checked_add(refill))
+    }
+}
@@ -0,0 +1,336 @@
+//! # Oblivion Metrics
+//!
+//! Oblivion reports its metrics to a process-wide [`MetricsRecorder`] installed with
+//! [`set_recorder`]. Nothing is recorded until a recorder is installed.
+//!
+//! The built-in [`PrometheusRecorder`] keeps the metrics in memory and renders them
+//! in the Prometheus text exposition format, optionally serving them over HTTP.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::exceptions::Exception;
+
+use super::server::recover_accept;
+
+/// Most scrapes served at once, further connections are closed right away.
+const MAX_SCRAPES: usize = 16;
+
+/// Deadline for a whole scrape, from reading the request to writing the metrics.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const CONNECTIONS_ACCEPTED: &str = "oblivion_connections_accepted_total";
+pub const CONNECTIONS_REJECTED: &str = "oblivion_connections_rejected_total";
+pub const HANDSHAKE_FAILURES: &str = "oblivion_handshake_failures_total";
+pub const ACTIVE_SESSIONS: &str = "oblivion_active_sessions";
+pub const REQUESTS: &str = "oblivion_requests_total";
+pub const REQUEST_DURATION: &str = "oblivion_request_duration_seconds";
+pub const BYTES_SENT: &str = "oblivion_bytes_sent_total";
+pub const BYTES_RECEIVED: &str = "oblivion_bytes_received_total";
+pub const DECRYPT_FAILURES: &str = "oblivion_decrypt_failures_total";
+
+/// Metric labels as `(name, value)` pairs.
+pub type Labels<'a> = &'a [(&'a str, &'a str)];
+
+/// Oblivion Metrics Recorder
+///
+/// Receives every metric reported by Oblivion, implement it to forward the metrics
+/// to any monitoring backend.
+pub trait MetricsRecorder: Send + Sync {
+    /// Add `value` to a monotonically increasing counter.
+    fn increment_counter(&self, name: &str, labels: Labels, value: u64);
+    /// Add `delta` to a gauge, which may be negative.
+    fn add_gauge(&self, name: &str, labels: Labels, delta: f64);
+    /// Record an observation into a histogram.
+    fn record_histogram(&self, name: &str, labels: Labels, value: f64);
+}
+
+static RECORDER: OnceLock<Arc<dyn MetricsRecorder>> = OnceLock::new();
+
+/// Install the process-wide metrics recorder, which can only be done once.
+pub fn set_recorder(recorder: Arc<dyn MetricsRecorder>) -> Result<(), Exception> {
+    RECORDER
+        .set(recorder)
+        .map_err(|_| Exception::InvalidConfig("metrics recorder is already set".to_string()))
+}
+
+/// The installed metrics recorder, if any.
+pub fn recorder() -> Option<&'static dyn MetricsRecorder> {
+    RECORDER.get().map(|recorder| recorder.as_ref())
+}
+
+#[inline]
+pub(crate) fn counter(name: &str, labels: Labels, value: u64) {
+    if let Some(recorder) = recorder() {
+        recorder.increment_counter(name, labels, value);
+    }
+}
+
+#[inline]
+pub(crate) fn gauge(name: &str, labels: Labels, delta: f64) {
+    if let Some(recorder) = recorder() {
+        recorder.add_gauge(name, labels, delta);
+    }
+}
+
+#[inline]
+pub(crate) fn histogram(name: &str, labels: Labels, value: f64) {
+    if let Some(recorder) = recorder() {
+        recorder.record_histogram(name, labels, value);
+    }
+}
+
+/// Classify an error into a short cause used as a metric label.
+pub(crate) fn cause(error: &anyhow::Error) -> &'static str {
+    match error.downcast_ref::<Exception>() {
+        Some(Exception::HandshakeTimeout { .. })
+        | Some(Exception::ReadTimeout { .. })
+        | Some(Exception::ConnectTimeout { .. }) => "timeout",
+        Some(Exception::ConnectionReset) => "reset",
+        Some(Exception::DecryptError { .. }) => "decrypt",
+        Some(Exception::InvalidHeader(_)) => "invalid_header",
+        Some(Exception::DataTooLarge { .. }) => "too_large",
+        Some(Exception::ConnectionRejected { .. }) => "rejected",
+        Some(_) => "protocol",
+        None if error.downcast_ref::<std::io::Error>().is_some() => "io",
+        None => "other",
+    }
+}
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    counters: BTreeMap<String, BTreeMap<String, u64>>,
+    gauges: BTreeMap<String, BTreeMap<String, f64>>,
+    histograms: BTreeMap<String, BTreeMap<String, Histogram>>,
+}
+
+/// Prometheus Metrics Recorder
+///
+/// Keeps all metrics in memory and renders them in the Prometheus text format.
+///
+/// ```rust
+/// use oblivion::models::metrics::{MetricsRecorder, PrometheusRecorder};
+///
+/// let recorder = PrometheusRecorder::new();
+/// recorder.increment_counter("oblivion_requests_total", &[("route", "/welcome")], 2);
+///
+/// assert!(recorder
+///     .render()
+///     .contains("oblivion_requests_total{route=\"/welcome\"} 2"));
+/// ```
+#[derive(Default)]
+pub struct PrometheusRecorder {
+    registry: Mutex<Registry>,
+}
+
+impl PrometheusRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap();
+        let mut output = String::new();
+        for (name, series) in registry.counters.iter() {
+            let _ = writeln!(output, "# TYPE {} counter", name);
+            for (labels, value) in series {
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        }
+        for (name, series) in registry.gauges.iter() {
+            let _ = writeln!(output, "# TYPE {} gauge", name);
+            for (labels, value) in series {
+                let _ = writeln!(output, "{}{} {}", name, labels, value);
+            }
+        }
+        for (name, series) in registry.histograms.iter() {
+            let _ = writeln!(output, "# TYPE {} histogram", name);
+            for (labels, histogram) in series {
+                let mut cumulative = 0;
+                for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                    cumulative += count;
+                    let _ = writeln!(
+                        output,
+                        "{}_bucket{} {}",
+                        name,
+                        with_label(labels, "le", &bound.to_string()),
+                        cumulative
+                    );
+                }
+                let _ = writeln!(
+                    output,
+                    "{}_bucket{} {}",
+                    name,
+                    with_label(labels, "le", "+Inf"),
+                    histogram.count
+                );
+                let _ = writeln!(output, "{}_sum{} {}", name, labels, histogram.sum);
+                let _ = writeln!(output, "{}_count{} {}", name, labels, histogram.count);
+            }
+        }
+        output
+    }
+
+    /// Serve the rendered metrics over plain HTTP on `address`.
+    ///
+    /// The endpoint answers any request with the current metrics, it is meant to be
+    /// bound to a local port scraped by Prometheus. At most 16 scrapes are served at
+    /// once, each within 10 seconds.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use oblivion::models::metrics::{MetricsRecorder, PrometheusRecorder};
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use tokio::net::TcpStream;
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let address = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    /// let recorder = Arc::new(PrometheusRecorder::new());
+    /// recorder.increment_counter("oblivion_requests_total", &[], 3);
+    /// recorder.serve(address).await?;
+    ///
+    /// // A connection that never sends its request does not hold up scrapes.
+    /// let _idle = TcpStream::connect(address).await?;
+    /// let mut scrape = TcpStream::connect(address).await?;
+    /// scrape.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").await?;
+    /// let mut response = String::new();
+    /// scrape.read_to_string(&mut response).await?;
+    /// assert!(response.contains("oblivion_requests_total 3"));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn serve(self: Arc<Self>, address: SocketAddr) -> Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(address).await?;
+        let scrapes = Arc::new(Semaphore::new(MAX_SCRAPES));
+        Ok(tokio::spawn(async move {
+            loop {
+                let (mut stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(error) => match recover_accept(error).await {
+                        Ok(()) => continue,
+                        Err(_) => return,
+                    },
+                };
+                let Ok(permit) = Arc::clone(&scrapes).try_acquire_owned() else {
+                    debug!(%peer, "too many pending scrapes, closing the connection");
+                    continue;
+                };
+                let recorder = Arc::clone(&self);
+                tokio::spawn(async move {
+                    let scrape = async {
+                        // Any request is answered, read its header so that closing
+                        // the stream does not reset it.
+                        let mut request = Vec::with_capacity(1024);
+                        let mut buffer = [0; 1024];
+                        while !request.windows(4).any(|end| end == b"\r\n\r\n")
+                            && request.len() < 8192
+                        {
+                            match stream.read(&mut buffer).await? {
+                                0 => break,
+                                len => request.extend_from_slice(&buffer[..len]),
+                            }
+                        }
+                        let body = recorder.render();
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        stream.write_all(response.as_bytes()).await?;
+                        stream.shutdown().await
+                    };
+                    match tokio::time::timeout(SCRAPE_TIMEOUT, scrape).await {
+                        Ok(Err(error)) => debug!(%peer, %error, "failed to serve metrics"),
+                        Err(_) => debug!(%peer, "metrics scrape timed out"),
+                        Ok(Ok(())) => {}
+                    }
+                    drop(permit);
+                });
+            }
+        }))
+    }
+}
+
+impl MetricsRecorder for PrometheusRecorder {
+    fn increment_counter(&self, name: &str, labels: Labels, value: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .counters
+            .entry(name.to_string())
+            .or_default()
+            .entry(format_labels(labels))
+            .or_default() += value;
+    }
+
+    fn add_gauge(&self, name: &str, labels: Labels, delta: f64) {
+        let mut registry = self.registry.lock().unwrap();
+        *registry
+            .gauges
+            .entry(name.to_string())
+            .or_default()
+            .entry(format_labels(labels))
+            .or_default() += delta;
+    }
+
+    fn record_histogram(&self, name: &str, labels: Labels, value: f64) {
+        let mut registry = self.registry.lock().unwrap();
+        let histogram = registry
+            .histograms
+            .entry(name.to_string())
+            .or_default()
+            .entry(format_labels(labels))
+            .or_default();
+        if let Some(index) = BUCKETS.iter().position(|bound| value <= *bound) {
+            histogram.buckets[index] += 1;
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+}
+
+fn format_labels(labels: Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+fn with_label(labels: &str, name: &str, value: &str) -> String {
+    match labels.strip_suffix('}') {
+        Some(labels) => format!("{},{}=\"{}\"}}", labels, name, value),
+        None => format!("{{{}=\"{}\"}}", name, value),
+    }
+}
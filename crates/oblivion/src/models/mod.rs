@@ -1,6 +1,20 @@
+pub mod access;
+pub mod acl;
+pub mod body;
 pub mod client;
+#[cfg(feature = "serde")]
+pub mod codec;
+pub mod compression;
+pub mod config;
+pub mod file;
 pub mod handler;
+pub mod heartbeat;
+pub mod limiter;
+pub mod metrics;
+pub mod mux;
 pub mod packet;
+pub mod pool;
+pub mod proxy;
 pub mod render;
 pub mod router;
 pub mod server;
@@ -0,0 +1,520 @@
+//! # Oblivion Stream Multiplexing
+//!
+//! Carries many independent streams over a single session, each with its own flow
+//! control window and close, in the manner of HTTP/2 or yamux.
+//!
+//! Every stream frame is a message with flag `5` whose content starts with the
+//! stream identifier and the frame kind. Clients open odd streams and servers even
+//! ones, stream `0` carries the frames concerning the whole session.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::exceptions::Exception;
+
+use super::session::Session;
+
+/// Flag of the messages carrying stream frames.
+const MUX_FLAG: u32 = 5;
+
+/// Bytes a stream may send before the peer grants more.
+pub const STREAM_WINDOW: u32 = 256 * 1024;
+
+/// Largest payload of a single data frame.
+pub const MAX_FRAME_SIZE: usize = 16 * 1024;
+
+/// Largest number of streams the peer may keep open at the same time.
+pub const MAX_STREAMS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Open = 0,
+    Data = 1,
+    Window = 2,
+    Close = 3,
+    Reset = 4,
+    GoAway = 5,
+}
+
+impl Kind {
+    fn from_u8(kind: u8) -> Option<Self> {
+        match kind {
+            0 => Some(Self::Open),
+            1 => Some(Self::Data),
+            2 => Some(Self::Window),
+            3 => Some(Self::Close),
+            4 => Some(Self::Reset),
+            5 => Some(Self::GoAway),
+            _ => None,
+        }
+    }
+}
+
+fn frame(id: u32, kind: Kind, data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(5 + data.len());
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.push(kind as u8);
+    frame.extend_from_slice(data);
+    frame
+}
+
+struct State {
+    send_window: u32,
+    recv_window: u32,
+    local_closed: bool,
+    remote_closed: bool,
+    reset: bool,
+}
+
+struct Channel {
+    state: Mutex<State>,
+    window: Notify,
+}
+
+struct Entry {
+    channel: Arc<Channel>,
+    /// Dropped once the peer closes its side, ending the stream for the reader.
+    inbound: Option<UnboundedSender<Vec<u8>>>,
+}
+
+struct Shared {
+    streams: Mutex<HashMap<u32, Entry>>,
+    outgoing: UnboundedSender<Vec<u8>>,
+    closed: AtomicBool,
+}
+
+impl Shared {
+    fn send(&self, id: u32, kind: Kind, data: &[u8]) -> Result<(), Exception> {
+        self.outgoing
+            .send(frame(id, kind, data))
+            .map_err(|_| Exception::ConnectionClosed)
+    }
+
+    fn register(self: &Arc<Self>, id: u32) -> MuxStream {
+        let channel = Arc::new(Channel {
+            state: Mutex::new(State {
+                send_window: STREAM_WINDOW,
+                recv_window: STREAM_WINDOW,
+                local_closed: false,
+                remote_closed: false,
+                reset: false,
+            }),
+            window: Notify::new(),
+        });
+        let (inbound, receiver) = unbounded_channel();
+        self.streams.lock().unwrap().insert(
+            id,
+            Entry {
+                channel: Arc::clone(&channel),
+                inbound: Some(inbound),
+            },
+        );
+        MuxStream {
+            id,
+            channel,
+            inbound: receiver,
+            shared: Arc::clone(self),
+            consumed: 0,
+        }
+    }
+
+    fn remove(&self, id: u32) {
+        self.streams.lock().unwrap().remove(&id);
+    }
+
+    /// Abort stream `id` on both sides.
+    fn reset(&self, id: u32) {
+        let entry = self.streams.lock().unwrap().remove(&id);
+        if let Some(entry) = entry {
+            entry.channel.state.lock().unwrap().reset = true;
+            entry.channel.window.notify_one();
+        }
+        let _ = self.send(id, Kind::Reset, &[]);
+    }
+
+    /// Tear down every stream once the session stops carrying frames.
+    fn shutdown(&self) {
+        self.closed.store(true, Ordering::Release);
+        for (_, entry) in self.streams.lock().unwrap().drain() {
+            let mut state = entry.channel.state.lock().unwrap();
+            if !state.remote_closed {
+                state.reset = true;
+            }
+            entry.channel.window.notify_one();
+        }
+    }
+}
+
+/// Oblivion Multiplexed Stream
+///
+/// One bidirectional stream of a [`Mux`], closed independently of the others.
+pub struct MuxStream {
+    id: u32,
+    channel: Arc<Channel>,
+    inbound: UnboundedReceiver<Vec<u8>>,
+    shared: Arc<Shared>,
+    consumed: u32,
+}
+
+impl MuxStream {
+    #[inline]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Send `data`, waiting for the peer to grant window whenever it runs out.
+    pub async fn send(&self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            {
+                let mut state = self.channel.state.lock().unwrap();
+                if state.reset {
+                    return Err(Exception::StreamReset { id: self.id }.into());
+                }
+                if state.local_closed || self.shared.closed.load(Ordering::Acquire) {
+                    return Err(Exception::ConnectionClosed.into());
+                }
+                if state.send_window > 0 {
+                    let len = data
+                        .len()
+                        .min(MAX_FRAME_SIZE)
+                        .min(state.send_window as usize);
+                    state.send_window -= len as u32;
+                    drop(state);
+                    self.shared.send(self.id, Kind::Data, &data[..len])?;
+                    data = &data[len..];
+                    continue;
+                }
+            }
+            self.channel.window.notified().await;
+        }
+        Ok(())
+    }
+
+    /// Receive the next data sent by the peer, `None` once the peer closed its side.
+    pub async fn recv(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(data) = self.inbound.recv().await else {
+            return match self.channel.state.lock().unwrap().reset {
+                true => Err(Exception::StreamReset { id: self.id }.into()),
+                false => Ok(None),
+            };
+        };
+
+        // Grant the window back once half of it was consumed.
+        self.consumed += data.len() as u32;
+        if self.consumed >= STREAM_WINDOW / 2 {
+            let mut state = self.channel.state.lock().unwrap();
+            if !state.remote_closed && !state.reset {
+                state.recv_window += self.consumed;
+                let _ = self
+                    .shared
+                    .send(self.id, Kind::Window, &self.consumed.to_be_bytes());
+            }
+            self.consumed = 0;
+        }
+        Ok(Some(data))
+    }
+
+    /// Stop sending, the peer may keep sending until it closes its side too.
+    pub async fn close(&self) -> Result<()> {
+        let remote_closed = {
+            let mut state = self.channel.state.lock().unwrap();
+            if state.reset {
+                return Err(Exception::StreamReset { id: self.id }.into());
+            }
+            if state.local_closed {
+                return Ok(());
+            }
+            state.local_closed = true;
+            state.remote_closed
+        };
+        self.shared.send(self.id, Kind::Close, &[])?;
+        if remote_closed {
+            self.shared.remove(self.id);
+        }
+        Ok(())
+    }
+
+    /// Abort the stream in both directions, discarding anything in flight.
+    pub async fn reset(&self) -> Result<()> {
+        self.shared.reset(self.id);
+        Ok(())
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        let finished = {
+            let state = self.channel.state.lock().unwrap();
+            state.reset || (state.local_closed && state.remote_closed)
+        };
+        if !finished {
+            self.shared.reset(self.id);
+        }
+    }
+}
+
+/// Oblivion Stream Multiplexer
+///
+/// Takes over the messages of a session until [`Mux::close`], both peers must
+/// multiplex the session at the same time. Each stream may send [`STREAM_WINDOW`]
+/// bytes ahead of what the peer has read, so a slow stream never stalls the others
+/// and buffered data stays bounded.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use oblivion::models::client::Client;
+/// use oblivion::models::mux::Mux;
+/// use oblivion::models::router::Router;
+/// use oblivion::models::server::Server;
+/// use oblivion::models::session::Session;
+/// use oblivion::path_route;
+/// use oblivion_codegen::async_route;
+/// use tokio::net::TcpListener;
+///
+/// #[async_route]
+/// async fn echo(session: Session) -> String {
+///     let mux = Mux::server(Arc::new(session));
+///     while let Some(mut stream) = mux.accept().await {
+///         tokio::spawn(async move {
+///             while let Ok(Some(data)) = stream.recv().await {
+///                 stream.send(&data).await.unwrap();
+///             }
+///             stream.close().await.unwrap();
+///         });
+///     }
+///     mux.close().await.unwrap();
+///     "done".to_string()
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let mut router = Router::new();
+/// path_route!(router, "/echo" => echo);
+/// let listener = TcpListener::bind("127.0.0.1:0").await?;
+/// let server = Server::builder(router).listener(listener).build();
+/// let address = server.local_addr().unwrap();
+/// tokio::spawn(async move { server.run().await });
+///
+/// let client = Client::connect(&format!("{}/echo", address)).await?;
+/// let mux = client.mux();
+/// let exchange = |text: &'static str| {
+///     let stream = mux.open();
+///     async move {
+///         let mut stream = stream.await?;
+///         stream.send(text.as_bytes()).await?;
+///         stream.close().await?;
+///         let echoed = stream.recv().await?.unwrap_or_default();
+///         assert_eq!(stream.recv().await?, None);
+///         anyhow::Ok(echoed)
+///     }
+/// };
+/// let (first, second) = tokio::try_join!(exchange("first"), exchange("second"))?;
+/// assert_eq!((&first[..], &second[..]), (&b"first"[..], &b"second"[..]));
+///
+/// mux.close().await?;
+/// assert_eq!(client.recv().await?.text()?, "done");
+/// # Ok(())
+/// # }
+/// ```
+pub struct Mux {
+    shared: Arc<Shared>,
+    next_id: AtomicU32,
+    incoming: tokio::sync::Mutex<UnboundedReceiver<MuxStream>>,
+    reader: Option<JoinHandle<()>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl Mux {
+    /// Multiplex the session of a client, which opens odd streams.
+    pub fn client(session: Arc<Session>) -> Self {
+        Self::new(session, 1)
+    }
+
+    /// Multiplex the session of a server, which opens even streams.
+    pub fn server(session: Arc<Session>) -> Self {
+        Self::new(session, 2)
+    }
+
+    fn new(session: Arc<Session>, first_id: u32) -> Self {
+        let (outgoing, outgoing_receiver) = unbounded_channel();
+        let (incoming, incoming_receiver) = unbounded_channel();
+        let shared = Arc::new(Shared {
+            streams: Mutex::new(HashMap::new()),
+            outgoing,
+            closed: AtomicBool::new(false),
+        });
+        let writer = tokio::spawn(write(Arc::clone(&session), outgoing_receiver));
+        let reader = tokio::spawn(read(
+            session,
+            Arc::clone(&shared),
+            incoming,
+            (first_id + 1) % 2,
+        ));
+        Self {
+            shared,
+            next_id: AtomicU32::new(first_id),
+            incoming: tokio::sync::Mutex::new(incoming_receiver),
+            reader: Some(reader),
+            writer: Some(writer),
+        }
+    }
+
+    /// Open a new stream to the peer.
+    pub async fn open(&self) -> Result<MuxStream> {
+        if self.shared.closed.load(Ordering::Acquire) {
+            return Err(Exception::ConnectionClosed.into());
+        }
+        let id = self.next_id.fetch_add(2, Ordering::Relaxed);
+        let stream = self.shared.register(id);
+        self.shared.send(id, Kind::Open, &[])?;
+        Ok(stream)
+    }
+
+    /// Wait for the next stream opened by the peer, `None` once the peer closed the multiplexer.
+    pub async fn accept(&self) -> Option<MuxStream> {
+        self.incoming.lock().await.recv().await
+    }
+
+    /// Close the multiplexer and wait for the peer to close it too.
+    ///
+    /// Open streams are reset and the session carries ordinary messages again.
+    /// Dropping the multiplexer instead leaves the session unusable.
+    pub async fn close(mut self) -> Result<()> {
+        self.shared.closed.store(true, Ordering::Release);
+        let _ = self.shared.send(0, Kind::GoAway, &[]);
+        if let Some(writer) = self.writer.take() {
+            writer.await?;
+        }
+        if let Some(reader) = self.reader.take() {
+            reader.await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        for task in [self.reader.take(), self.writer.take()]
+            .into_iter()
+            .flatten()
+        {
+            task.abort();
+        }
+        self.shared.shutdown();
+    }
+}
+
+/// Write queued frames one message at a time, up to the frame closing the multiplexer.
+async fn write(session: Arc<Session>, mut outgoing: UnboundedReceiver<Vec<u8>>) {
+    while let Some(frame) = outgoing.recv().await {
+        let last = frame[4] == Kind::GoAway as u8;
+        if let Err(error) = session.send_frame(MUX_FLAG, frame).await {
+            debug!(%error, "failed to write a stream frame");
+            break;
+        }
+        if last {
+            break;
+        }
+    }
+}
+
+/// Dispatch frames from the peer to their streams until the peer closes the multiplexer.
+async fn read(
+    session: Arc<Session>,
+    shared: Arc<Shared>,
+    incoming: UnboundedSender<MuxStream>,
+    parity: u32,
+) {
+    loop {
+        let content = match session.recv().await {
+            Ok(response) if response.flag == MUX_FLAG && response.content.len() >= 5 => {
+                response.content
+            }
+            Ok(response) => {
+                debug!(
+                    flag = response.flag,
+                    "unexpected message on a multiplexed session"
+                );
+                break;
+            }
+            Err(error) => {
+                debug!(%error, "multiplexed session ended");
+                break;
+            }
+        };
+        let id = u32::from_be_bytes([content[0], content[1], content[2], content[3]]);
+        let data = &content[5..];
+
+        match Kind::from_u8(content[4]) {
+            Some(Kind::GoAway) => break,
+            Some(Kind::Open) => {
+                let accepted = {
+                    let streams = shared.streams.lock().unwrap();
+                    id % 2 == parity
+                        && !streams.contains_key(&id)
+                        && streams.len() < MAX_STREAMS
+                        && !incoming.is_closed()
+                };
+                if accepted {
+                    // A stream nobody accepts anymore is reset when dropped.
+                    let _ = incoming.send(shared.register(id));
+                } else {
+                    let _ = shared.send(id, Kind::Reset, &[]);
+                }
+            }
+            Some(Kind::Data) => {
+                let mut violated = false;
+                if let Some(entry) = shared.streams.lock().unwrap().get(&id) {
+                    let mut state = entry.channel.state.lock().unwrap();
+                    if state.recv_window < data.len() as u32 {
+                        violated = true;
+                    } else if let Some(inbound) = &entry.inbound {
+                        state.recv_window -= data.len() as u32;
+                        let _ = inbound.send(data.to_vec());
+                    }
+                }
+                if violated {
+                    debug!(id, "stream exceeded its window");
+                    shared.reset(id);
+                }
+            }
+            Some(Kind::Window) if data.len() == 4 => {
+                if let Some(entry) = shared.streams.lock().unwrap().get(&id) {
+                    let increment = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                    let mut state = entry.channel.state.lock().unwrap();
+                    state.send_window = state.send_window.saturating_add(increment);
+                    entry.channel.window.notify_one();
+                }
+            }
+            Some(Kind::Close) => {
+                let mut streams = shared.streams.lock().unwrap();
+                if let Some(entry) = streams.get_mut(&id) {
+                    entry.inbound = None;
+                    let local_closed = {
+                        let mut state = entry.channel.state.lock().unwrap();
+                        state.remote_closed = true;
+                        state.local_closed
+                    };
+                    if local_closed {
+                        streams.remove(&id);
+                    }
+                }
+            }
+            Some(Kind::Reset) => {
+                if let Some(entry) = shared.streams.lock().unwrap().remove(&id) {
+                    entry.channel.state.lock().unwrap().reset = true;
+                    entry.channel.window.notify_one();
+                }
+            }
+            // Unknown kinds are left to future versions.
+            Some(Kind::Window) | None => {}
+        }
+    }
+    shared.shutdown();
+}
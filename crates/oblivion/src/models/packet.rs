@@ -1,7 +1,7 @@
 //! # Oblivion Packets Encapsulation
 use crate::exceptions::Exception;
-use crate::utils::decryptor::decrypt_bytes;
-use crate::utils::encryptor::{encrypt_bytes, encrypt_plaintext};
+use crate::utils::decryptor::decrypt_bytes_with_aad;
+use crate::utils::encryptor::{encrypt_bytes_with_aad, encrypt_plaintext};
 use crate::utils::gear::Socket;
 use crate::utils::generator::{generate_random_salt, SharedKey};
 use crate::utils::parser::length;
@@ -9,10 +9,27 @@ use crate::utils::parser::length;
 use anyhow::Result;
 use serde_json::Value;
 
+use super::metrics;
+
 use ring::agreement::{EphemeralPrivateKey, UnparsedPublicKey, X25519};
 
 const STOP_FLAG: [u8; 4] = u32::MIN.to_be_bytes();
 
+/// Serialize a message, the `OSC` of `flag` followed by the `OED` of `data` sealed
+/// along with the associated data `aad`.
+///
+/// Writing a message at once keeps concurrent writers from interleaving their frames.
+pub fn message(flag: u32, aes_key: &[u8], data: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>> {
+    let mut bytes = flag.to_be_bytes().to_vec();
+    bytes.extend(
+        OED::new(aes_key)
+            .associate(aad)
+            .from_bytes(data)?
+            .to_bytes()?,
+    );
+    Ok(bytes)
+}
+
 pub struct OSC {
     pub status_code: u32,
 }
@@ -34,6 +51,43 @@ impl OSC {
     }
 }
 
+/// Oblivion Rejection Frame
+///
+/// Sent in plain text in place of the server's public key when a connection is
+/// refused before the handshake, so that the peer learns why it was turned away.
+/// A zero public key length marks the frame, followed by the length-prefixed reason.
+pub struct ORF {
+    pub reason: String,
+}
+
+impl ORF {
+    pub fn new(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Read the reason of a rejection whose zero marker was already consumed.
+    pub async fn from_stream(stream: &Socket) -> Result<Self> {
+        let len_reason = stream.recv_usize().await?;
+        if len_reason > 2048 {
+            return Err(Exception::DataTooLarge { size: len_reason }.into());
+        }
+        Ok(Self {
+            reason: stream.recv_str(len_reason).await?,
+        })
+    }
+
+    pub async fn to_stream(&self, stream: &Socket) -> Result<()> {
+        let reason = self.reason.as_bytes();
+        let mut plain_bytes = STOP_FLAG.to_vec();
+        plain_bytes.extend_from_slice(&length(reason)?);
+        plain_bytes.extend_from_slice(reason);
+        stream.send(&plain_bytes).await?;
+        Ok(())
+    }
+}
+
 pub struct OKE {
     public_key: UnparsedPublicKey<Vec<u8>>,
     private_key: Option<EphemeralPrivateKey>,
@@ -63,6 +117,12 @@ impl OKE {
 
     pub async fn from_stream(&mut self, stream: &Socket) -> Result<&mut Self> {
         let remote_public_key_length = stream.recv_usize().await?;
+        if remote_public_key_length > 2048 {
+            return Err(Exception::DataTooLarge {
+                size: remote_public_key_length,
+            }
+            .into());
+        }
         let remote_public_key_bytes = stream.recv(remote_public_key_length).await?;
         self.remote_public_key = Some(UnparsedPublicKey::new(&X25519, remote_public_key_bytes));
         let mut shared_key = SharedKey::new(
@@ -75,6 +135,19 @@ impl OKE {
 
     pub async fn from_stream_with_salt(&mut self, stream: &Socket) -> Result<&mut Self> {
         let remote_public_key_length = stream.recv_usize().await?;
+        if remote_public_key_length == 0 {
+            let rejection = ORF::from_stream(stream).await?;
+            return Err(Exception::ConnectionRejected {
+                reason: rejection.reason,
+            }
+            .into());
+        }
+        if remote_public_key_length > 2048 {
+            return Err(Exception::DataTooLarge {
+                size: remote_public_key_length,
+            }
+            .into());
+        }
         let remote_public_key_bytes = stream.recv(remote_public_key_length).await?;
         self.remote_public_key = Some(UnparsedPublicKey::new(&X25519, remote_public_key_bytes));
         let salt_length = stream.recv_usize().await?;
@@ -123,6 +196,8 @@ pub struct OED<'a> {
     tag: Vec<u8>,
     nonce: Vec<u8>,
     chunk_count: u32,
+    max_size: usize,
+    aad: Vec<u8>,
 }
 
 impl<'a> OED<'a> {
@@ -134,9 +209,23 @@ impl<'a> OED<'a> {
             tag: Vec::new(),
             nonce: Vec::new(),
             chunk_count: 0,
+            max_size: usize::MAX,
+            aad: Vec::new(),
         }
     }
 
+    /// Authenticate `aad` along with the data, which must then be opened with the same `aad`.
+    pub fn associate(&mut self, aad: &[u8]) -> &mut Self {
+        self.aad = aad.to_vec();
+        self
+    }
+
+    /// Reject incoming data whose encrypted size exceeds `max_size` bytes.
+    pub fn limit(&mut self, max_size: usize) -> &mut Self {
+        self.max_size = max_size;
+        self
+    }
+
     pub fn from_json_or_string(&mut self, json_or_str: String) -> Result<&mut Self, Exception> {
         (self.encrypted_data, self.tag, self.nonce) = encrypt_plaintext(json_or_str, self.aes_key)?;
         Ok(self)
@@ -154,13 +243,20 @@ impl<'a> OED<'a> {
     }
 
     pub fn from_bytes(&mut self, data: Vec<u8>) -> Result<&mut Self, Exception> {
-        (self.encrypted_data, self.tag, self.nonce) = encrypt_bytes(data, self.aes_key)?;
+        (self.encrypted_data, self.tag, self.nonce) =
+            encrypt_bytes_with_aad(data, self.aes_key, &self.aad)?;
         Ok(self)
     }
 
     pub async fn from_stream(&mut self, stream: &Socket) -> Result<&mut Self> {
         let len_nonce = stream.recv_usize().await?;
         let len_tag = stream.recv_usize().await?;
+        if len_nonce > 2048 || len_tag > 2048 {
+            return Err(Exception::DataTooLarge {
+                size: len_nonce.max(len_tag),
+            }
+            .into());
+        }
 
         self.nonce = stream.recv(len_nonce).await?;
         self.tag = stream.recv(len_tag).await?;
@@ -174,6 +270,12 @@ impl<'a> OED<'a> {
                 self.encrypted_data = encrypted_data;
                 break;
             }
+            if encrypted_data.len() + prefix > self.max_size {
+                return Err(Exception::DataTooLarge {
+                    size: encrypted_data.len() + prefix,
+                }
+                .into());
+            }
 
             let mut add: Vec<u8> = Vec::new();
             while add.len() != prefix {
@@ -184,34 +286,43 @@ impl<'a> OED<'a> {
             self.chunk_count += 1;
         }
 
-        match decrypt_bytes(
+        match decrypt_bytes_with_aad(
             self.encrypted_data.clone(),
             &self.tag,
             self.aes_key,
             &self.nonce,
+            &self.aad,
         ) {
             Ok(data) => {
                 self.data = Some(data);
                 Ok(self)
             }
-            Err(error) => Err(Exception::DecryptError { error }.into()),
+            Err(error) => {
+                metrics::counter(metrics::DECRYPT_FAILURES, &[], 1);
+                Err(Exception::DecryptError { error }.into())
+            }
         }
     }
 
     pub async fn to_stream(&mut self, stream: &Socket) -> Result<()> {
-        stream.send(&self.plain_data()?).await?;
+        stream.send(&self.to_bytes()?).await
+    }
+
+    /// Serialize the whole encrypted data, so that it is written to the stream at once.
+    pub fn to_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut bytes = self.plain_data()?;
 
         self.chunk_count = 0;
         let chunks = self.encrypted_data.chunks(1024);
         for chunk in chunks {
             let chunk_size = chunk.len() as u32;
-            stream.send(&chunk_size.to_be_bytes()).await?;
-            stream.send(chunk).await?;
+            bytes.extend_from_slice(&chunk_size.to_be_bytes());
+            bytes.extend_from_slice(chunk);
             self.chunk_count += 1;
         }
-        stream.send(&STOP_FLAG).await?;
+        bytes.extend_from_slice(&STOP_FLAG);
 
-        Ok(())
+        Ok(bytes)
     }
 
     pub fn plain_data(&self) -> Result<Vec<u8>> {
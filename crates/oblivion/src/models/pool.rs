@@ -0,0 +1,196 @@
+//! # Oblivion Connection Pool
+//!
+//! Keeps the sessions opened to persistent servers and reuses them for requests to
+//! any entrance of the same host, saving a connection and a handshake per request.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Result;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+use crate::utils::parser::OblivionPath;
+
+use super::client::{Client, ClientBuilder, Request, Response};
+use super::config::PoolConfig;
+use super::file::FILE_FLAG;
+
+struct Idle {
+    client: Client,
+    since: Instant,
+}
+
+/// Sessions to a single host.
+struct Host {
+    permits: Semaphore,
+    idle: Mutex<Vec<Idle>>,
+}
+
+/// Oblivion Connection Pool
+///
+/// Sessions are only reused with servers keeping them open, see
+/// `ServerBuilder::persistent`, any other server gets a new connection per request.
+///
+/// ```rust
+/// use oblivion::models::pool::Pool;
+/// use oblivion::models::router::Router;
+/// use oblivion::models::server::Server;
+/// use tokio::net::TcpListener;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let listener = TcpListener::bind("127.0.0.1:0").await?;
+/// let server = Server::builder(Router::new())
+///     .listener(listener)
+///     .persistent(true)
+///     .build();
+/// let address = server.local_addr().unwrap();
+/// tokio::spawn(async move { server.run().await });
+///
+/// let pool = Pool::default();
+/// for entrance in ["/first", "/second"] {
+///     let response = pool.fetch(&format!("{}{}", address, entrance)).await?;
+///     assert_eq!(response.status(), 404);
+/// }
+/// assert_eq!(pool.idle(), 1);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pool {
+    builder: ClientBuilder,
+    config: PoolConfig,
+    hosts: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Self::new(ClientBuilder::new(), PoolConfig::default())
+    }
+}
+
+impl Pool {
+    /// Pool sessions opened by `builder`.
+    pub fn new(builder: ClientBuilder, config: PoolConfig) -> Self {
+        Self {
+            builder,
+            config,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[inline]
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    /// Request `location`, such as `127.0.0.1:7076/welcome`, and return the final response.
+    ///
+    /// An idle session to the host is reused when it passes the health checks,
+    /// otherwise a new one is opened. Messages sent by the handler before its final
+    /// response are skipped, handlers exchanging messages need a dedicated `Client`.
+    /// The content of a file response is read in full and returned as the content.
+    pub async fn fetch(&self, location: &str) -> Result<Response> {
+        self.send(location, Request::default()).await
+    }
+
+    /// Request `location` with the headers and body of `request`, see [`Pool::fetch`].
+    pub async fn send(&self, location: &str, request: Request) -> Result<Response> {
+        let path = OblivionPath::new(location)?;
+        let host = self.host(&path);
+        let _permit = host.permits.acquire().await?;
+
+        let client = match self.checkout(&host).await {
+            Some(client) => {
+                client.request(path.get_entrance(), request).await?;
+                client
+            }
+            None => self.builder.connect_with(location, request).await?,
+        };
+        let mut file = None;
+        let mut response = loop {
+            let response = client.recv().await?;
+            match response.flag {
+                1 | 3 => break response,
+                FILE_FLAG => {
+                    let mut content = Vec::new();
+                    client.recv_body().copy_to(&mut content).await?;
+                    file = Some(content);
+                }
+                _ => {}
+            }
+        };
+        if let Some(content) = file {
+            response.content = content;
+        }
+
+        if client.session.reusable() {
+            host.idle.lock().unwrap().push(Idle {
+                client,
+                since: Instant::now(),
+            });
+        }
+        Ok(response)
+    }
+
+    /// Number of idle sessions kept open across all hosts.
+    pub fn idle(&self) -> usize {
+        self.hosts
+            .lock()
+            .unwrap()
+            .values()
+            .map(|host| host.idle.lock().unwrap().len())
+            .sum()
+    }
+
+    /// Close every idle session.
+    pub fn clear(&self) {
+        for host in self.hosts.lock().unwrap().values() {
+            host.idle.lock().unwrap().clear();
+        }
+    }
+
+    fn host(&self, path: &OblivionPath) -> Arc<Host> {
+        let key = match path.get_socket_path() {
+            Some(socket_path) => socket_path.to_string(),
+            None => format!(
+                "{}://{}:{}",
+                path.get_protocol(),
+                path.get_host(),
+                path.get_port()
+            ),
+        };
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts.entry(key).or_insert_with(|| {
+            Arc::new(Host {
+                permits: Semaphore::new(self.config.max_per_host.max(1)),
+                idle: Mutex::new(Vec::new()),
+            })
+        });
+        Arc::clone(host)
+    }
+
+    /// Take the most recently used idle session of `host` that is still healthy,
+    /// expired sessions are evicted on the way.
+    ///
+    /// Pings and pongs received while the session was idle are handled by the health check.
+    async fn checkout(&self, host: &Host) -> Option<Client> {
+        loop {
+            let entry = {
+                let mut idle = host.idle.lock().unwrap();
+                if let Some(timeout) = self.config.idle_timeout {
+                    idle.retain(|entry| entry.since.elapsed() < timeout);
+                }
+                idle.pop()?
+            };
+            if self.config.health_check && !entry.client.session.is_idle().await {
+                debug!(
+                    entrance = entry.client.entrance,
+                    "pooled session was closed"
+                );
+                continue;
+            }
+            return Some(entry.client);
+        }
+    }
+}
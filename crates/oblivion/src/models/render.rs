@@ -1,14 +1,118 @@
 //! # Oblivion Render
+use std::collections::HashMap;
+
 use anyhow::Result;
-use serde_json::Value;
+use bytes::Bytes;
+use serde_json::{json, Value};
 
 use crate::exceptions::Exception;
 
+#[cfg(feature = "serde")]
+use super::codec::Codec;
+use super::file::FileResponse;
+use super::session::Session;
+
+/// Flag of the message carrying the head of the response following it.
+pub(crate) const HEAD_FLAG: u32 = 8;
+/// Protocol version from which clients read head messages, older ones are only sent the content.
+pub(crate) const HEAD_VERSION: (u32, u32) = (2, 1);
+
+/// Status and headers of a response, sent encrypted ahead of its content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseHead {
+    pub status: u32,
+    /// Headers by lowercase name.
+    pub headers: HashMap<String, String>,
+}
+
+impl Default for ResponseHead {
+    fn default() -> Self {
+        Self {
+            status: 200,
+            headers: HashMap::new(),
+        }
+    }
+}
+
+impl ResponseHead {
+    pub fn to_json(&self) -> Value {
+        json!({ "status": self.status, "headers": self.headers })
+    }
+
+    pub fn from_json(json: &Value) -> Result<Self, Exception> {
+        let invalid = || Exception::InvalidHeader(format!("invalid response head: {}", json));
+        let status = json["status"].as_u64().ok_or_else(invalid)?;
+        let headers = json["headers"]
+            .as_object()
+            .ok_or_else(invalid)?
+            .iter()
+            .map(|(name, value)| Some((name.to_ascii_lowercase(), value.as_str()?.to_string())))
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            status: u32::try_from(status).map_err(|_| invalid())?,
+            headers,
+        })
+    }
+}
+
+/// Oblivion Response
+///
+/// Handlers may set the status and headers of any response, raw bytes may also be
+/// tagged with a content type. Clients announcing `Oblivion/2.1` or later find them
+/// on `Response`, older ones are only sent the content.
+///
+/// ```rust
+/// use oblivion::models::render::BaseResponse;
+///
+/// let response = BaseResponse::from(vec![0x89, b'P', b'N', b'G'])
+///     .with_content_type("image/png")
+///     .with_status(201)
+///     .with_header("Cache-Control", "no-store");
+///
+/// assert_eq!(response.status(), 201);
+/// assert_eq!(response.content_type(), Some("image/png"));
+/// assert_eq!(response.head().headers["cache-control"], "no-store");
+/// assert_eq!(response.as_bytes()?, b"\x89PNG");
+/// # Ok::<(), oblivion::exceptions::Exception>(())
+/// ```
+///
+/// The head is left out for `Oblivion/2.0` clients, which do not know its message:
+///
+/// ```rust
+/// use oblivion::models::{render::BaseResponse, session::Session};
+/// use oblivion::utils::gear::Socket;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// for (version, status) in [("2.0", 200), ("2.1", 404)] {
+///     let (client, server) = tokio::io::duplex(64 * 1024);
+///     let mut client = Session::new_with_header(
+///         format!("CONNECT /missing Oblivion/{}", version),
+///         Socket::from_stream(client, None),
+///     )?;
+///     let mut server = Session::new(Socket::from_stream(server, None))?;
+///     tokio::try_join!(client.handshake(0), server.handshake(1))?;
+///
+///     let missing = BaseResponse::TextResponse("Not found".to_string()).with_status(404);
+///     server.response(missing).await?;
+///     let response = client.recv().await?;
+///     assert_eq!(response.status(), status);
+///     assert_eq!(response.text()?, "Not found");
+/// }
+/// # Ok(())
+/// # }
+/// ```
 #[derive(Clone)]
 pub enum BaseResponse {
-    FileResponse(String),
+    /// File streamed from disk, see [`FileResponse`].
+    FileResponse(FileResponse),
     TextResponse(String),
     JsonResponse(Value),
+    /// Raw bytes and their optional content type.
+    BytesResponse(Vec<u8>, Option<String>),
+    /// Response with a status or headers set, see [`BaseResponse::with_status`].
+    HeadedResponse(Box<BaseResponse>, ResponseHead),
 }
 
 impl BaseResponse {
@@ -19,8 +123,122 @@ impl BaseResponse {
             }),
             Self::TextResponse(text) => Ok(text.as_bytes().to_vec()),
             Self::JsonResponse(data) => Ok(data.to_string().as_bytes().to_vec()),
+            Self::BytesResponse(bytes, _) => Ok(bytes.clone()),
+            Self::HeadedResponse(body, _) => body.as_bytes(),
+        }
+    }
+
+    /// Serialize `value` as a JSON response.
+    #[cfg(feature = "serde")]
+    pub fn serialize<T: serde::Serialize + ?Sized>(value: &T) -> Result<Self> {
+        Ok(Self::JsonResponse(serde_json::to_value(value)?))
+    }
+
+    /// Encode `value` with `codec` as a bytes response tagged with its content type.
+    #[cfg(feature = "serde")]
+    pub fn encode<T: serde::Serialize + ?Sized>(codec: Codec, value: &T) -> Result<Self> {
+        Ok(Self::BytesResponse(
+            codec.encode(value)?,
+            Some(codec.content_type().to_string()),
+        ))
+    }
+
+    /// The response without its status and headers.
+    pub fn body(&self) -> &Self {
+        match self {
+            Self::HeadedResponse(body, _) => body.body(),
+            body => body,
+        }
+    }
+
+    /// Status of the response, `200` unless set otherwise.
+    pub fn status(&self) -> u32 {
+        match self {
+            Self::HeadedResponse(_, head) => head.status,
+            _ => 200,
+        }
+    }
+
+    /// Content type sent along with the response, if any.
+    pub fn content_type(&self) -> Option<&str> {
+        match self {
+            Self::BytesResponse(_, content_type) => content_type.as_deref(),
+            Self::HeadedResponse(body, head) => head
+                .headers
+                .get("content-type")
+                .map(String::as_str)
+                .or_else(|| body.content_type()),
+            _ => None,
+        }
+    }
+
+    /// Status and headers of the response, including its content type.
+    pub fn head(&self) -> ResponseHead {
+        let mut head = match self {
+            Self::HeadedResponse(_, head) => head.clone(),
+            _ => ResponseHead::default(),
+        };
+        if let Some(content_type) = self.content_type() {
+            head.headers
+                .insert("content-type".to_string(), content_type.to_string());
+        }
+        head
+    }
+
+    /// Tag the response with `content_type`.
+    pub fn with_content_type(self, content_type: &str) -> Self {
+        match self {
+            Self::BytesResponse(bytes, _) => {
+                Self::BytesResponse(bytes, Some(content_type.to_string()))
+            }
+            response => response.with_header("content-type", content_type),
         }
     }
+
+    /// Answer with `status` instead of `200`.
+    pub fn with_status(mut self, status: u32) -> Self {
+        self.head_mut().status = status;
+        self
+    }
+
+    /// Set the header `name`, which is case insensitive, to `value`.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.head_mut()
+            .headers
+            .insert(name.to_ascii_lowercase(), value.to_string());
+        self
+    }
+
+    fn head_mut(&mut self) -> &mut ResponseHead {
+        if !matches!(self, Self::HeadedResponse(..)) {
+            let body = std::mem::replace(self, Self::TextResponse(String::new()));
+            *self = Self::HeadedResponse(Box::new(body), ResponseHead::default());
+        }
+        match self {
+            Self::HeadedResponse(_, head) => head,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Encode the response as a message with `flag`, preceded by its head unless it
+    /// is the default one or the client predates [`HEAD_VERSION`], sealed by `session`.
+    /// The content of a file is sent apart, see [`FileResponse`].
+    pub(crate) fn to_messages(&self, flag: u32, session: &Session) -> Result<Vec<u8>> {
+        let head = self.head();
+        let (major, minor) = HEAD_VERSION;
+        let mut messages = match head == ResponseHead::default()
+            || !session.request.supports_version(major, minor)
+        {
+            true => Vec::new(),
+            false => session.seal(HEAD_FLAG, head.to_json().to_string().into_bytes(), &[])?,
+        };
+        let content = match self.body() {
+            Self::FileResponse(_) => Vec::new(),
+            body => body.as_bytes()?,
+        };
+        messages.extend(session.seal(flag, content, &[])?);
+        Ok(messages)
+    }
 }
 
 impl TryInto<Vec<u8>> for BaseResponse {
@@ -48,3 +266,44 @@ impl From<Value> for BaseResponse {
         Self::JsonResponse(data)
     }
 }
+
+/// Handlers marked with `async_route` may return raw bytes, which are sent as is.
+///
+/// ```rust
+/// use oblivion::models::client::Client;
+/// use oblivion::models::router::{RoutePath, RouteType, Router};
+/// use oblivion::models::server::Server;
+/// use oblivion::models::session::Session;
+/// use oblivion_codegen::async_route;
+/// use tokio::net::TcpListener;
+///
+/// #[async_route]
+/// fn png(_session: Session) -> anyhow::Result<Vec<u8>> {
+///     Ok(vec![0x89, b'P', b'N', b'G'])
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let mut router = Router::new();
+/// router.route(RoutePath::new("/png", RouteType::Path), png);
+/// let listener = TcpListener::bind("127.0.0.1:0").await?;
+/// let server = Server::builder(router).listener(listener).build();
+/// let address = server.local_addr().unwrap();
+/// tokio::spawn(async move { server.run().await });
+///
+/// let client = Client::connect(&format!("{}/png", address)).await?;
+/// assert_eq!(client.recv().await?.content, b"\x89PNG");
+/// # Ok(())
+/// # }
+/// ```
+impl From<Vec<u8>> for BaseResponse {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::BytesResponse(bytes, None)
+    }
+}
+
+impl From<Bytes> for BaseResponse {
+    fn from(bytes: Bytes) -> Self {
+        Self::BytesResponse(bytes.into(), None)
+    }
+}
@@ -1,19 +1,53 @@
 //! # Oblivion Router
 use crate::types::Handler;
+use crate::utils::parser::OblivionRequest;
 
+use super::acl::AccessList;
 use super::handler::not_found;
+use super::limiter::RateLimiter;
 use anyhow::Result;
 use regex::Regex;
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct Route {
     handler: Handler,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    access: Option<Arc<AccessList>>,
 }
 
 impl Route {
     pub fn new(handler: Handler) -> Self {
-        Self { handler }
+        Self {
+            handler,
+            rate_limiter: None,
+            access: None,
+        }
+    }
+
+    /// Only let peers permitted by `access` reach this route.
+    pub fn access(mut self, access: AccessList) -> Self {
+        self.access = Some(Arc::new(access));
+        self
+    }
+
+    /// Check the peer of `request` against the access list of this route.
+    ///
+    /// Peers without an IP address, such as Unix domain socket peers, are only
+    /// permitted by an empty list or one that sets [`AccessList::allow_local`].
+    pub fn permits(&self, request: &OblivionRequest) -> bool {
+        match (&self.access, request.get_ip()) {
+            (None, _) => true,
+            (Some(access), "") => access.permits_peer(None),
+            (Some(access), ip) => ip.parse().is_ok_and(|address| access.permits(&address)),
+        }
+    }
+
+    /// Throttle requests to this route with `limiter`.
+    pub fn rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(Arc::new(limiter));
+        self
     }
 
     #[inline]
@@ -43,6 +77,11 @@ impl RoutePath {
         }
     }
 
+    #[inline]
+    pub fn route(&self) -> &str {
+        &self.route
+    }
+
     #[inline]
     pub fn check(&self, entrance: &str) -> Result<bool> {
         if self.route_type == RouteType::RegexPath {
@@ -59,6 +98,7 @@ impl RoutePath {
 #[derive(Clone)]
 pub struct Router {
     routes: HashMap<RoutePath, Route>,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl Default for Router {
@@ -71,11 +111,18 @@ impl Router {
     pub fn new() -> Self {
         Self {
             routes: HashMap::new(),
+            rate_limiter: None,
         }
     }
 
     pub fn route(&mut self, path: RoutePath, handler: Handler) -> &mut Self {
-        self.routes.insert(path, Route { handler });
+        self.routes.insert(path, Route::new(handler));
+        self
+    }
+
+    /// Throttle requests to every route with `limiter`.
+    pub fn rate_limit(&mut self, limiter: RateLimiter) -> &mut Self {
+        self.rate_limiter = Some(Arc::new(limiter));
         self
     }
 
@@ -83,12 +130,58 @@ impl Router {
         self.routes.insert(path, route);
     }
 
-    pub fn get_handler(&self, path: &str) -> Result<Handler> {
+    pub fn get_route(&self, path: &str) -> Result<Option<(&RoutePath, &Route)>> {
         for (route_path, route) in self.routes.iter() {
             if route_path.check(path)? {
-                return Ok(route.get_handler());
+                return Ok(Some((route_path, route)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn get_handler(&self, path: &str) -> Result<Handler> {
+        Ok(self
+            .get_route(path)?
+            .map_or(not_found, |(_, route)| route.get_handler()))
+    }
+
+    /// Consume a token from the global and route rate limiters for `request`.
+    ///
+    /// Returns `false` if the request exceeds either limit, consuming no token at all.
+    ///
+    /// ```rust
+    /// use oblivion::models::handler::not_found;
+    /// use oblivion::models::limiter::{RateLimit, RateLimiter};
+    /// use oblivion::models::router::{Route, Router};
+    /// use oblivion::utils::parser::OblivionRequest;
+    ///
+    /// let mut router = Router::new();
+    /// router.rate_limit(RateLimiter::new(RateLimit::new(2, 0.001)));
+    /// let route = Route::new(not_found).rate_limit(RateLimiter::new(RateLimit::new(1, 0.001)));
+    /// let mut request = OblivionRequest::new("CONNECT /limited Oblivion/2.0")?;
+    /// request.set_remote_peer(&"127.0.0.1:7076".parse()?);
+    ///
+    /// assert!(router.permit(Some(&route), &request));
+    /// assert!(!router.permit(Some(&route), &request));
+    /// // The throttled request left the global budget untouched.
+    /// assert!(router.permit(None, &request));
+    /// assert!(!router.permit(None, &request));
+    /// # Ok::<(), anyhow::Error>(())
+    /// ```
+    pub fn permit(&self, route: Option<&Route>, request: &OblivionRequest) -> bool {
+        let limiters = [
+            route.and_then(|route| route.rate_limiter.as_ref()),
+            self.rate_limiter.as_ref(),
+        ];
+        let limiters = limiters.into_iter().flatten().collect::<Vec<_>>();
+        for (index, limiter) in limiters.iter().enumerate() {
+            if !limiter.check(request) {
+                for limiter in &limiters[..index] {
+                    limiter.refund(request);
+                }
+                return false;
             }
         }
-        Ok(not_found)
+        true
     }
 }
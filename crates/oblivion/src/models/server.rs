@@ -1,140 +1,618 @@
 //! # Oblivion Server
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crate::utils::gear::Socket;
-#[cfg(not(feature = "bench"))]
 use crate::VERSION;
 
 use anyhow::{Error, Result};
-use chrono::Local;
-use colored::Colorize;
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Local};
 #[cfg(feature = "bench")]
 use std::process;
-use tokio::net::{TcpListener, TcpStream};
-#[cfg(feature = "perf")]
-use tokio::time::Instant;
+use tokio::io::AsyncReadExt;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream};
+use tokio::sync::Semaphore;
+use tracing::{debug, debug_span, error, info, info_span, warn, Instrument};
 
-use super::packet::{OED, OSC};
+use crate::exceptions::Exception;
+use crate::types::Handler;
+
+use super::access::{AccessLogConfig, AccessLogEntry, AccessLogger};
+use super::acl::Cidr;
+use super::config::{CompressionConfig, HeartbeatConfig, ServerConfig};
+use super::handler::not_found;
+use super::limiter::{RateLimit, RateLimiter};
+use super::metrics;
+use super::packet::ORF;
+use super::render::BaseResponse;
 use super::router::Router;
 use super::session::Session;
 
+/// Identifier of the next accepted connection, recorded on its `session` span.
+static SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Pause after an accept error such as running out of file descriptors, giving
+/// finishing connections time to release theirs.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Most refused connections answered at once, further ones are closed right away
+/// so that a flood over the connection caps cannot pile up tasks and sockets.
+const MAX_PENDING_REJECTIONS: usize = 64;
+
+/// Tracks an active session and reports its traffic once it ends.
+struct SessionMetrics {
+    socket: Arc<Socket>,
+}
+
+impl SessionMetrics {
+    fn new(socket: Arc<Socket>) -> Self {
+        metrics::gauge(metrics::ACTIVE_SESSIONS, &[], 1.0);
+        Self { socket }
+    }
+}
+
+impl Drop for SessionMetrics {
+    fn drop(&mut self) {
+        let side = [("side", "server")];
+        metrics::gauge(metrics::ACTIVE_SESSIONS, &[], -1.0);
+        metrics::counter(metrics::BYTES_SENT, &side, self.socket.bytes_written());
+        metrics::counter(metrics::BYTES_RECEIVED, &side, self.socket.bytes_read());
+    }
+}
+
+fn record_request(route: &str, status: u32, started: Instant) {
+    let status = status.to_string();
+    metrics::counter(
+        metrics::REQUESTS,
+        &[("route", route), ("status", &status)],
+        1,
+    );
+    metrics::histogram(
+        metrics::REQUEST_DURATION,
+        &[("route", route)],
+        started.elapsed().as_secs_f64(),
+    );
+}
+
+/// What became of a request, filled in while it is handled for the access log.
+struct Outcome {
+    request: String,
+    entrance: String,
+    status: u32,
+    socket: Arc<Socket>,
+    identity: Arc<ArcSwapOption<String>>,
+    time: DateTime<Local>,
+    /// Traffic of the connection before the request.
+    bytes: (u64, u64),
+}
+
+impl Outcome {
+    fn new(session: &Session) -> Self {
+        let socket = Arc::clone(&session.socket);
+        Self {
+            request: "-".to_string(),
+            entrance: "-".to_string(),
+            status: 500,
+            bytes: (socket.bytes_read(), socket.bytes_written()),
+            socket,
+            identity: session.identity_slot(),
+            time: Local::now(),
+        }
+    }
+}
+
 #[inline]
-async fn _handle(router: &Router, stream: TcpStream, peer: SocketAddr) -> Result<()> {
-    #[cfg(feature = "perf")]
-    let now = std::time::Instant::now();
-    stream.set_ttl(20)?;
-    stream.set_nodelay(true)?;
-    stream.set_linger(Some(std::time::Duration::from_secs(0)))?;
-    socket2::SockRef::from(&stream).set_keepalive(true)?;
-    let mut session = Session::new(Socket::new(stream))?;
-
-    if let Err(error) = session.handshake(1).await {
-        eprintln!(
-            "{} -> [{}] \"{}\" {}",
-            peer.ip().to_string().cyan(),
-            Local::now().format("%d/%m/%Y %H:%M:%S"),
-            "CONNECT - Oblivion/2.0".yellow(),
-            "500".red()
+async fn _handle(
+    router: &Router,
+    config: &ServerConfig,
+    access_log: Option<&AccessLogger>,
+    mut socket: Socket,
+) -> Result<()> {
+    let started = Instant::now();
+    let peer = socket.peer();
+    socket.set_read_timeout(config.idle_timeout);
+    let mut session = Session::new(socket)?;
+    session.set_max_message_size(config.max_message_size);
+    session.set_compression(config.compression.clone());
+    let _metrics = SessionMetrics::new(Arc::clone(&session.socket));
+    let mut outcome = Outcome::new(&session);
+
+    let handshake = match config.handshake_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, session.handshake(1))
+            .instrument(debug_span!("handshake"))
+            .await
+            .unwrap_or_else(|_| Err(Exception::HandshakeTimeout { timeout }.into())),
+        None => {
+            session
+                .handshake(1)
+                .instrument(debug_span!("handshake"))
+                .await
+        }
+    };
+    if let Err(error) = handshake {
+        outcome.status = match error.downcast_ref::<Exception>() {
+            Some(Exception::HandshakeTimeout { .. }) => 408,
+            _ => 400,
+        };
+        warn!(%error, outcome = "handshake_failed", "handshake failed");
+        metrics::counter(
+            metrics::HANDSHAKE_FAILURES,
+            &[("side", "server"), ("cause", metrics::cause(&error))],
+            1,
         );
-        eprintln!("{}", error.to_string().bright_red());
+        if let Some(logger) = access_log {
+            log_access(logger, outcome, peer);
+        }
         #[cfg(feature = "bench")]
         {
-            eprintln!("Handshake failed in benchmark test unexpectedly.");
+            error!("Handshake failed in benchmark test unexpectedly.");
             process::exit(1);
         }
         #[cfg(not(feature = "bench"))]
         return Ok(());
     }
-
-    #[cfg(feature = "perf")]
-    println!(
-        "握手时长: {}μs",
-        now.elapsed().as_micros().to_string().bright_magenta()
-    );
-
-    #[cfg(not(any(feature = "perf", feature = "bench")))]
-    let header = session.header().to_string();
-    #[cfg(not(any(feature = "perf", feature = "bench")))]
-    let ip_addr = session.get_ip().to_string();
-    let aes_key = session.aes_key;
-
-    #[cfg(not(any(feature = "perf", feature = "bench")))]
-    println!(
-        "{} -> [{}] \"{}\" {}",
-        ip_addr.cyan(),
-        Local::now().format("%d/%m/%Y %H:%M:%S"),
-        header.green(),
-        "OK".cyan()
+    debug!(
+        handshake_us = started.elapsed().as_micros() as u64,
+        "handshake completed"
     );
+    if let Some(heartbeat) = &config.heartbeat {
+        session.start_heartbeat(heartbeat.clone())?;
+    }
 
-    #[cfg(feature = "perf")]
-    let now = Instant::now();
+    let mut started = started;
+    let mut served = 0;
+    loop {
+        served += 1;
+        let persistent = config.persistent && config.max_requests.is_none_or(|max| served < max);
+        outcome.request = session.header.clone();
+        outcome.entrance = session.request.entrance.clone();
+        let span = info_span!("request", entrance = session.request.entrance.as_str());
+        let result = respond(router, session, persistent, &mut outcome, started)
+            .instrument(span)
+            .await;
+        if let Some(logger) = access_log {
+            log_access(logger, outcome, peer);
+        }
+        let Some(mut next) = result? else {
+            return Ok(());
+        };
 
-    let socket = Arc::clone(&session.socket);
+        outcome = Outcome::new(&next);
+        if !next.accept_request(config.persistent_timeout).await? {
+            debug!("session ended by the peer");
+            if next.closed().await {
+                drain(&next.socket).await;
+            }
+            return Ok(());
+        }
+        outcome.time = Local::now();
+        started = Instant::now();
+        session = next;
+    }
+}
 
-    let callback = router.get_handler(&session.request.entrance)?(session).await?;
+/// Dispatch the request of `session` through `router` and send the response.
+///
+/// Returns the session awaiting the next request when it stays `persistent`.
+async fn respond(
+    router: &Router,
+    session: Session,
+    persistent: bool,
+    outcome: &mut Outcome,
+    started: Instant,
+) -> Result<Option<Session>> {
+    let request_started = Instant::now();
+    let matched = router.get_route(&session.request.entrance)?;
+    let route_label = matched.map_or("not_found", |(path, _)| path.route());
+    let route = matched.map(|(_, route)| route);
+    if !route.is_none_or(|route| route.permits(&session.request)) {
+        warn!(status = 403, outcome = "denied", "access denied");
+        record_request(route_label, 403, request_started);
+        outcome.status = 403;
+        session.error(403, "Access denied.").await?;
+        return Ok(None);
+    }
+    if !router.permit(route, &session.request) {
+        warn!(status = 429, outcome = "throttled", "too many requests");
+        record_request(route_label, 429, request_started);
+        outcome.status = 429;
+        session.error(429, "Too many requests.").await?;
+        return Ok(None);
+    }
+    let handler = route.map_or(not_found as Handler, |route| route.get_handler());
 
-    #[cfg(feature = "perf")]
-    println!(
-        "业务函数时长: {}μs",
-        now.elapsed().as_micros().to_string().bright_magenta()
-    );
+    info!(header = session.header(), "request accepted");
+    let socket = Arc::clone(&session.socket);
+    let responder = session.renew();
 
-    #[cfg(feature = "perf")]
     let now = Instant::now();
+    let callback = match handler(session).instrument(info_span!("handler")).await {
+        Ok(callback) => callback,
+        Err(error) => {
+            record_request(route_label, 500, request_started);
+            return Err(error);
+        }
+    };
+    debug!(
+        handler_us = now.elapsed().as_micros() as u64,
+        "handler completed"
+    );
 
-    OSC::from_u32(1).to_stream(&socket).await?;
-    OED::new(&aes_key)
-        .from_bytes(callback.as_bytes()?)?
-        .to_stream(&socket)
-        .await?;
+    let status = callback.status();
+    // Nothing more can be sent once the handler closed the session itself.
+    if responder.local_closed() {
+        debug!("session closed by the handler");
+        drain(&socket).await;
+        record_request(route_label, status, request_started);
+        outcome.status = status;
+        return Ok(None);
+    }
+    let file = match callback.body() {
+        BaseResponse::FileResponse(file) => match file.open().await {
+            Ok(opened) => Some(opened),
+            Err(error) => {
+                let (status, reason) = file_error(&error);
+                warn!(status, outcome = "file", %error, "{}", reason);
+                record_request(route_label, status, request_started);
+                outcome.status = status;
+                responder.error(status, reason).await?;
+                return Ok(None);
+            }
+        },
+        _ => None,
+    };
 
-    socket.close().await?;
+    async {
+        // Flag 3 tells the client the session stays open for its next request.
+        let flag = if persistent { 3 } else { 1 };
+        if let Some((file, meta)) = file {
+            debug!(file = meta.name, size = meta.size, "sending file");
+            responder.send_opened(file, &meta).await?;
+        }
+        socket
+            .send(&callback.to_messages(flag, &responder)?)
+            .await?;
+        match persistent {
+            true => Ok(()),
+            false => socket.close().await,
+        }
+    }
+    .instrument(debug_span!("response"))
+    .await?;
+    record_request(route_label, status, request_started);
+    outcome.status = status;
 
-    #[cfg(feature = "perf")]
-    println!(
-        "结束函数时长: {}μs",
-        now.elapsed().as_micros().to_string().bright_magenta()
+    info!(
+        status,
+        outcome = "ok",
+        bytes_in = socket.bytes_read() - outcome.bytes.0,
+        bytes_out = socket.bytes_written() - outcome.bytes.1,
+        duration_us = started.elapsed().as_micros() as u64,
+        "response sent"
     );
 
-    #[cfg(not(any(feature = "perf", feature = "bench")))]
-    println!(
-        "{} <- [{}] \"{}\" {}",
-        ip_addr.cyan(),
-        Local::now().format("%d/%m/%Y %H:%M:%S"),
-        header.green(),
-        "OK".cyan()
+    Ok(persistent.then_some(responder))
+}
+
+/// Status and reason sent for a file that cannot be served.
+fn file_error(error: &Error) -> (u32, &'static str) {
+    match error.downcast_ref::<Exception>() {
+        Some(Exception::PathTraversal { .. }) => return (403, "Access denied."),
+        Some(Exception::RangeNotSatisfiable { .. }) => return (416, "Range not satisfiable."),
+        _ => {}
+    }
+    match error.downcast_ref::<io::Error>().map(io::Error::kind) {
+        Some(io::ErrorKind::NotFound) => (404, "File not found."),
+        Some(io::ErrorKind::PermissionDenied) => (403, "Access denied."),
+        _ => (500, "Failed to read the file."),
+    }
+}
+
+fn log_access(logger: &AccessLogger, outcome: Outcome, peer: Option<SocketAddr>) {
+    let entry = AccessLogEntry {
+        ip: peer.map_or("-".to_string(), |peer| peer.ip().to_string()),
+        port: peer.map_or(0, |peer| peer.port()),
+        identity: outcome
+            .identity
+            .load_full()
+            .map(|identity| identity.to_string()),
+        time: outcome.time,
+        request: outcome.request,
+        entrance: outcome.entrance,
+        status: outcome.status,
+        bytes_in: outcome.socket.bytes_read() - outcome.bytes.0,
+        bytes_out: outcome.socket.bytes_written() - outcome.bytes.1,
+        duration: (Local::now() - outcome.time).to_std().unwrap_or_default(),
+    };
+    if let Err(error) = logger.log(&entry) {
+        error!(%error, "failed to write the access log");
+    }
+}
+
+/// Handle an accepted connection, TCP socket options must already be applied.
+pub async fn handle(
+    router: Arc<Router>,
+    config: Arc<ServerConfig>,
+    access_log: Option<Arc<AccessLogger>>,
+    socket: Socket,
+) {
+    let span = info_span!(
+        "session",
+        id = SESSION_ID.fetch_add(1, Ordering::Relaxed),
+        peer = socket
+            .peer()
+            .map_or("-".to_string(), |peer| peer.to_string()),
     );
+    async move {
+        if let Err(error) = _handle(&router, &config, access_log.as_deref(), socket).await {
+            error!(%error, outcome = "error", "session failed");
+            #[cfg(feature = "bench")]
+            {
+                error!("An error occurred in handling runtime unexpectedly.");
+                process::exit(1);
+            }
+        }
+    }
+    .instrument(span)
+    .await
+}
 
-    Ok(())
+/// Refuse a connection before the handshake.
+///
+/// The rejection frame is written where possible, then the connection is drained
+/// briefly so that closing it does not reset the frame before the peer reads it.
+async fn reject(socket: Socket, status: u32, reason: &str) {
+    let peer = socket
+        .peer()
+        .map_or("-".to_string(), |peer| peer.to_string());
+    warn!(%peer, status, outcome = "rejected", reason, "connection rejected");
+    let rejected = async {
+        ORF::new(reason).to_stream(&socket).await?;
+        socket.close().await
+    };
+    if let Ok(Ok(())) = tokio::time::timeout(Duration::from_secs(1), rejected).await {
+        drain(&socket).await;
+    }
 }
 
-pub async fn handle(router: Arc<Router>, stream: TcpStream, peer: SocketAddr) {
-    #[cfg(feature = "perf")]
-    let now = Instant::now();
-    #[cfg(feature = "perf")]
-    println!("=================");
-    if let Err(error) = _handle(&router, stream, peer).await {
-        eprintln!(
-            "{} <-> [{}] \"{}\" {}",
-            peer.ip().to_string().cyan(),
-            Local::now().format("%d/%m/%Y %H:%M:%S"),
-            "CONNECT - Oblivion/2.0".yellow(),
-            "501".red()
+/// Refuse a connection in the background, or close it right away once
+/// [`MAX_PENDING_REJECTIONS`] refusals are already in flight.
+fn spawn_reject(
+    rejections: &Arc<Semaphore>,
+    config: &Arc<ServerConfig>,
+    socket: Socket,
+    status: u32,
+    reason: &'static str,
+) {
+    let Ok(permit) = Arc::clone(rejections).try_acquire_owned() else {
+        debug!(
+            status,
+            reason, "too many pending rejections, closing the connection"
         );
-        eprintln!("{}", error.to_string().bright_red());
-        #[cfg(feature = "bench")]
-        {
-            eprintln!("An error occurred in handling runtime unexpectedly.");
-            process::exit(1);
+        return;
+    };
+    let config = Arc::clone(config);
+    tokio::spawn(async move {
+        if let Some(socket) = upgrade(&config, socket).await {
+            reject(socket, status, reason).await
+        }
+        drop(permit);
+    });
+}
+
+/// Get over an error accepting a connection, returning it if the listener is unusable.
+///
+/// Errors of a single connection are skipped, others such as running out of file
+/// descriptors are logged and followed by a short pause before accepting again.
+pub(crate) async fn recover_accept(error: io::Error) -> io::Result<()> {
+    match error.kind() {
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported => {
+            error!(%error, "listener failed");
+            Err(error)
+        }
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::Interrupted => {
+            debug!(%error, "failed to accept a connection");
+            Ok(())
+        }
+        _ => {
+            warn!(%error, "failed to accept a connection, pausing");
+            tokio::time::sleep(ACCEPT_BACKOFF).await;
+            Ok(())
+        }
+    }
+}
+
+/// Discard anything the peer sends until it ends the connection, for up to a second,
+/// so that closing the connection does not reset frames the peer has yet to read.
+async fn drain(socket: &Socket) {
+    let drained = async {
+        let mut reader = socket.reader.lock().await;
+        let mut buffer = [0; 1024];
+        while reader.read(&mut buffer).await? != 0 {}
+        Ok::<(), Error>(())
+    };
+    let _ = tokio::time::timeout(Duration::from_secs(1), drained).await;
+}
+
+/// Accept the WebSocket upgrade of a connection when the server listens for WebSocket clients.
+async fn upgrade(config: &ServerConfig, socket: Socket) -> Option<Socket> {
+    #[cfg(feature = "websocket")]
+    if let Some(path) = &config.websocket {
+        let peer = socket.peer();
+        let upgraded = crate::utils::websocket::accept(socket, path);
+        let upgraded = match config.handshake_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, upgraded)
+                .await
+                .unwrap_or_else(|_| Err(Exception::HandshakeTimeout { timeout }.into())),
+            None => upgraded.await,
+        };
+        return match upgraded {
+            Ok(socket) => Some(socket),
+            Err(error) => {
+                debug!(?peer, %error, "WebSocket upgrade failed");
+                metrics::counter(
+                    metrics::HANDSHAKE_FAILURES,
+                    &[("side", "server"), ("cause", "websocket")],
+                    1,
+                );
+                None
+            }
+        };
+    }
+    #[cfg(not(feature = "websocket"))]
+    let _ = config;
+    Some(socket)
+}
+
+/// Live connection counters enforcing the global and per-IP connection caps.
+struct ConnectionTracker {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    connections: Mutex<(usize, HashMap<IpAddr, usize>)>,
+}
+
+impl ConnectionTracker {
+    fn new(config: &ServerConfig) -> Self {
+        Self {
+            max_connections: config.max_connections,
+            max_connections_per_ip: config.max_connections_per_ip,
+            connections: Mutex::new((0, HashMap::new())),
+        }
+    }
+
+    /// Count a connection from `ip`, peers without an IP address only count towards the global cap.
+    fn acquire(self: &Arc<Self>, ip: Option<IpAddr>) -> Result<ConnectionGuard, &'static str> {
+        let mut connections = self.connections.lock().unwrap();
+        let (total, per_ip) = &mut *connections;
+        if self.max_connections.is_some_and(|max| *total >= max) {
+            return Err("Too many connections to the server.");
+        }
+        if let Some(ip) = ip {
+            let count = per_ip.entry(ip).or_insert(0);
+            if self.max_connections_per_ip.is_some_and(|max| *count >= max) {
+                return Err("Too many connections from your address.");
+            }
+            *count += 1;
+        }
+        *total += 1;
+        Ok(ConnectionGuard {
+            tracker: Arc::clone(self),
+            ip,
+        })
+    }
+}
+
+struct ConnectionGuard {
+    tracker: Arc<ConnectionTracker>,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.tracker.connections.lock().unwrap();
+        let (total, per_ip) = &mut *connections;
+        *total -= 1;
+        if let Some(ip) = &self.ip {
+            if let Some(count) = per_ip.get_mut(ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(ip);
+                }
+            }
+        }
+    }
+}
+
+/// Bind `address` with a listen queue of `backlog` pending connections.
+async fn bind(address: &str, backlog: u32) -> Result<TcpListener> {
+    let mut last_error = None;
+    for address in lookup_host(address).await? {
+        let socket = if address.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.set_reuseaddr(true)?;
+        match socket.bind(address).and_then(|_| socket.listen(backlog)) {
+            Ok(listener) => return Ok(listener),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(match last_error {
+        Some(error) => Error::from(error),
+        None => Error::from(Exception::InvalidOblivion {
+            entrance: address.to_string(),
+        }),
+    })
+}
+
+/// Apply the socket options of `config` to an accepted TCP connection.
+fn configure(stream: &TcpStream, config: &ServerConfig) -> io::Result<()> {
+    stream.set_ttl(config.ttl)?;
+    stream.set_nodelay(config.nodelay)?;
+    if config.linger.is_some() {
+        stream.set_linger(config.linger)?;
+    }
+    socket2::SockRef::from(stream).set_keepalive(config.keepalive)
+}
+
+/// Bind a Unix domain socket at `path`, replacing a stale socket file left behind.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    async fn accept(&self, config: &ServerConfig) -> io::Result<Socket> {
+        match self {
+            Self::Tcp(listener) => loop {
+                let (stream, peer) = listener.accept().await?;
+                match configure(&stream, config) {
+                    Ok(()) => return Ok(Socket::new(stream)),
+                    Err(error) => warn!(%peer, %error, "failed to configure connection"),
+                }
+            },
+            #[cfg(unix)]
+            Self::Unix(listener) => Ok(Socket::new_unix(listener.accept().await?.0)),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        match self {
+            Self::Tcp(listener) => Ok(format!("Oblivion://{}/", listener.local_addr()?)),
+            #[cfg(unix)]
+            Self::Unix(listener) => Ok(format!(
+                "oblivion+unix://{}",
+                listener
+                    .local_addr()?
+                    .as_pathname()
+                    .map_or("".into(), |path| path.display().to_string())
+            )),
         }
     }
-    #[cfg(feature = "perf")]
-    println!(
-        "总执行时长: {}μs\n=================",
-        now.elapsed().as_micros().to_string().bright_magenta()
-    );
 }
 
 /// Oblivion Server
@@ -171,71 +649,410 @@ pub async fn handle(router: Arc<Router>, stream: TcpStream, peer: SocketAddr) {
 /// # }
 /// ```
 pub struct Server {
-    host: String,
-    port: i32,
+    address: String,
+    #[cfg(unix)]
+    unix: Option<PathBuf>,
+    listener: Option<Listener>,
     router: Arc<Router>,
+    config: Arc<ServerConfig>,
 }
 
 impl Server {
     pub fn new(host: &str, port: i32, router: Router) -> Self {
-        Self {
-            host: host.to_string(),
-            port,
-            router: Arc::new(router),
+        ServerBuilder::new(router)
+            .address(&format!("{}:{}", host, port))
+            .build()
+    }
+
+    /// Start configuring a server for `router`, see [`ServerBuilder`].
+    pub fn builder(router: Router) -> ServerBuilder {
+        ServerBuilder::new(router)
+    }
+
+    #[inline]
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Address of the pre-bound TCP listener, if the server was built with one.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match &self.listener {
+            Some(Listener::Tcp(listener)) => listener.local_addr().ok(),
+            _ => None,
         }
     }
 
     pub async fn run(&self) -> Result<()> {
-        #[cfg(not(feature = "bench"))]
-        println!("Performing system checks...\n");
-
-        let address = format!("{}:{}", self.host, self.port);
+        debug!("Performing system checks...");
+        if let Some(heartbeat) = &self.config.heartbeat {
+            heartbeat.validate()?;
+        }
 
-        let tcp = match TcpListener::bind(&address).await {
-            Ok(tcp) => tcp,
-            Err(error) => {
-                eprintln!(
-                    "{}",
-                    format!(
-                        "Destination address [{}] is already occupied!",
-                        address.bright_magenta()
-                    )
-                    .red()
-                );
-                return Err(Error::from(error));
-            }
+        let bound;
+        let listener = match &self.listener {
+            Some(listener) => listener,
+            None => match self.bind().await {
+                Ok(listener) => {
+                    bound = listener;
+                    &bound
+                }
+                Err(error) => {
+                    error!(
+                        address = %self.address,
+                        %error,
+                        "Destination address is already occupied!"
+                    );
+                    return Err(error);
+                }
+            },
+        };
+        let address = listener.local_addr()?;
+        let access_log = match &self.config.access_log {
+            Some(config) => Some(Arc::new(AccessLogger::open(config.clone())?)),
+            None => None,
         };
 
         tokio::spawn(async move {
             match tokio::signal::ctrl_c().await {
                 Ok(_) => {}
-                Err(e) => {
-                    eprintln!("{}", e.to_string().red());
+                Err(error) => {
+                    error!(%error, "Unable to listen for the shutdown signal");
                     std::process::exit(1);
                 }
             }
             std::process::exit(0);
         });
 
-        #[cfg(not(feature = "bench"))]
-        println!(
-            "Oblivion version {}, using '{}'",
-            VERSION.bright_yellow(),
-            "ring".bright_green()
+        info!(
+            version = VERSION,
+            backend = "ring",
+            "Oblivion server starting"
         );
+        info!("Starting server at {}", address);
+        #[cfg(feature = "websocket")]
+        if let Some(path) = &self.config.websocket {
+            info!("Accepting WebSocket upgrades on {}", path);
+        }
+        info!("Quit the server by CTRL-BREAK.");
 
-        #[cfg(not(feature = "bench"))]
-        println!(
-            "Starting server at {}",
-            format!("Oblivion://{}:{}/", self.host, self.port).bright_cyan()
-        );
-        #[cfg(not(feature = "bench"))]
-        println!("Quit the server by CTRL-BREAK.\n");
+        let tracker = Arc::new(ConnectionTracker::new(&self.config));
+        let rejections = Arc::new(Semaphore::new(MAX_PENDING_REJECTIONS));
+
+        loop {
+            let socket = match listener.accept(&self.config).await {
+                Ok(socket) => socket,
+                Err(error) => {
+                    recover_accept(error).await?;
+                    continue;
+                }
+            };
+            let ip = socket.peer().map(|peer| peer.ip());
+            if !self.config.access.permits_peer(ip) {
+                metrics::counter(metrics::CONNECTIONS_REJECTED, &[("reason", "access")], 1);
+                spawn_reject(&rejections, &self.config, socket, 403, "Access denied.");
+                continue;
+            }
+            match tracker.acquire(ip) {
+                Ok(guard) => {
+                    metrics::counter(metrics::CONNECTIONS_ACCEPTED, &[], 1);
+                    let router = Arc::clone(&self.router);
+                    let config = Arc::clone(&self.config);
+                    let access_log = access_log.clone();
+                    tokio::spawn(async move {
+                        if let Some(socket) = upgrade(&config, socket).await {
+                            handle(router, config, access_log, socket).await;
+                        }
+                        drop(guard);
+                    });
+                }
+                Err(reason) => {
+                    metrics::counter(metrics::CONNECTIONS_REJECTED, &[("reason", "limit")], 1);
+                    spawn_reject(&rejections, &self.config, socket, 503, reason);
+                }
+            }
+        }
+    }
+
+    async fn bind(&self) -> Result<Listener> {
+        #[cfg(unix)]
+        if let Some(path) = &self.unix {
+            return Ok(Listener::Unix(bind_unix(path)?));
+        }
+        Ok(Listener::Tcp(
+            bind(&self.address, self.config.backlog).await?,
+        ))
+    }
+}
+
+/// Oblivion Server Builder
+///
+/// Configures the listening address and the [`ServerConfig`] of a [`Server`].
+/// Binding an already-bound `TcpListener` makes it possible to serve on an
+/// ephemeral port and learn the port before the server runs.
+///
+/// # Example
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use oblivion::models::server::Server;
+/// # use oblivion::models::router::Router;
+/// # use tokio::net::TcpListener;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let listener = TcpListener::bind("127.0.0.1:0").await?;
+/// let server = Server::builder(Router::new())
+///     .listener(listener)
+///     .handshake_timeout(Some(Duration::from_secs(3)))
+///     .max_connections(Some(512))
+///     .build();
+///
+/// assert_ne!(server.local_addr().unwrap().port(), 0);
+/// # Ok(())
+/// # }
+/// ```
+pub struct ServerBuilder {
+    address: String,
+    #[cfg(unix)]
+    unix: Option<PathBuf>,
+    listener: Option<Listener>,
+    router: Router,
+    config: ServerConfig,
+}
 
-        while let Ok((stream, peer)) = tcp.accept().await {
-            tokio::spawn(handle(Arc::clone(&self.router), stream, peer));
+impl ServerBuilder {
+    pub fn new(router: Router) -> Self {
+        Self {
+            address: "127.0.0.1:7076".to_string(),
+            #[cfg(unix)]
+            unix: None,
+            listener: None,
+            router,
+            config: ServerConfig::default(),
         }
+    }
+
+    /// Listen on a `host:port` address, resolved when the server runs.
+    pub fn address(mut self, address: &str) -> Self {
+        self.address = address.to_string();
+        self
+    }
+
+    /// Listen on a socket address.
+    pub fn bind(mut self, address: SocketAddr) -> Self {
+        self.address = address.to_string();
+        self
+    }
+
+    /// Serve on an already-bound listener instead of binding an address.
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(Listener::Tcp(listener));
+        self
+    }
+
+    /// Listen on a Unix domain socket at `path` instead of a TCP address.
+    ///
+    /// A stale socket file at `path` is replaced when the server runs. IP access
+    /// lists and per-IP limits do not apply to Unix domain socket peers, use the
+    /// file permissions of the socket to restrict them.
+    #[cfg(unix)]
+    pub fn unix(mut self, path: impl Into<PathBuf>) -> Self {
+        self.unix = Some(path.into());
+        self
+    }
+
+    /// Serve on an already-bound Unix domain socket listener.
+    #[cfg(unix)]
+    pub fn unix_listener(mut self, listener: UnixListener) -> Self {
+        self.listener = Some(Listener::Unix(listener));
+        self
+    }
+
+    /// Replace the whole configuration.
+    pub fn config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+        self
+    }
 
-        Ok(())
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.config.ttl = ttl;
+        self
+    }
+
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.config.nodelay = nodelay;
+        self
+    }
+
+    pub fn linger(mut self, linger: Option<Duration>) -> Self {
+        self.config.linger = linger;
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: bool) -> Self {
+        self.config.keepalive = keepalive;
+        self
+    }
+
+    pub fn handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.handshake_timeout = timeout;
+        self
+    }
+
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.idle_timeout = timeout;
+        self
+    }
+
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.config.max_message_size = size;
+        self
+    }
+
+    /// Compress messages of clients accepting one of the configured encodings.
+    ///
+    /// ```rust
+    /// use oblivion::models::client::ClientBuilder;
+    /// use oblivion::models::compression::Encoding;
+    /// use oblivion::models::config::CompressionConfig;
+    /// use oblivion::models::render::BaseResponse;
+    /// use oblivion::models::router::{RoutePath, RouteType, Router};
+    /// use oblivion::models::server::Server;
+    /// use oblivion::models::session::Session;
+    /// use oblivion::types::ServerResponse;
+    /// use oblivion_codegen::async_route;
+    /// use tokio::net::TcpListener;
+    ///
+    /// #[async_route]
+    /// async fn repeat(session: Session) -> ServerResponse {
+    ///     let text = String::from_utf8(session.recv().await?.content)?;
+    ///     Ok(BaseResponse::TextResponse(text.repeat(64)))
+    /// }
+    ///
+    /// # #[cfg(feature = "zstd")]
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let mut router = Router::new();
+    /// router.route(RoutePath::new("/repeat", RouteType::Path), repeat);
+    /// let listener = TcpListener::bind("127.0.0.1:0").await?;
+    /// let compression = CompressionConfig::new(vec![Encoding::Zstd]);
+    /// let server = Server::builder(router)
+    ///     .listener(listener)
+    ///     .compression(Some(compression.clone()))
+    ///     .build();
+    /// let address = server.local_addr().unwrap();
+    /// tokio::spawn(async move { server.run().await });
+    ///
+    /// let client = ClientBuilder::new()
+    ///     .compression(Some(compression))
+    ///     .connect(&format!("{}/repeat", address))
+    ///     .await?;
+    /// client.send("oblivion".repeat(512).into_bytes()).await?;
+    /// let response = client.recv().await?;
+    /// assert_eq!(response.text()?, "oblivion".repeat(512 * 64));
+    /// // Both ways were compressed with the encoding the server announced.
+    /// assert!(client.session.socket.bytes_written() < 1024);
+    /// assert!(client.session.socket.bytes_read() < 64 * 1024);
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(not(feature = "zstd"))]
+    /// # fn main() {}
+    /// ```
+    pub fn compression(mut self, compression: Option<CompressionConfig>) -> Self {
+        self.config.compression = compression;
+        self
+    }
+
+    /// Ping clients as configured, closing sessions whose client stopped answering.
+    pub fn heartbeat(mut self, heartbeat: Option<HeartbeatConfig>) -> Self {
+        self.config.heartbeat = heartbeat;
+        self
+    }
+
+    pub fn max_connections(mut self, connections: Option<usize>) -> Self {
+        self.config.max_connections = connections;
+        self
+    }
+
+    pub fn max_connections_per_ip(mut self, connections: Option<usize>) -> Self {
+        self.config.max_connections_per_ip = connections;
+        self
+    }
+
+    /// Let peers in `cidr` connect, any peer outside the allowed ranges is rejected.
+    pub fn allow(mut self, cidr: Cidr) -> Self {
+        self.config.access.allow.push(cidr);
+        self
+    }
+
+    /// Reject peers in `cidr` right after accepting them.
+    pub fn deny(mut self, cidr: Cidr) -> Self {
+        self.config.access.deny.push(cidr);
+        self
+    }
+
+    /// Let peers without an IP address, such as Unix domain socket peers, connect
+    /// despite the allowed and denied ranges.
+    pub fn allow_local(mut self, allow_local: bool) -> Self {
+        self.config.access.allow_local = allow_local;
+        self
+    }
+
+    /// Keep sessions open after a response so that clients may send further requests
+    /// without a new handshake.
+    pub fn persistent(mut self, persistent: bool) -> Self {
+        self.config.persistent = persistent;
+        self
+    }
+
+    /// Close persistent sessions after serving `requests` requests.
+    pub fn max_requests(mut self, requests: Option<usize>) -> Self {
+        self.config.max_requests = requests;
+        self
+    }
+
+    /// Close persistent sessions left without a new request for `timeout`.
+    pub fn persistent_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.config.persistent_timeout = timeout;
+        self
+    }
+
+    /// Write an access log line for every handled request.
+    pub fn access_log(mut self, access_log: AccessLogConfig) -> Self {
+        self.config.access_log = Some(access_log);
+        self
+    }
+
+    /// Accept Oblivion clients over WebSocket upgrades on `path` instead of raw TCP.
+    #[cfg(feature = "websocket")]
+    pub fn websocket(mut self, path: &str) -> Self {
+        self.config.websocket = Some(path.to_string());
+        self
+    }
+
+    /// Length of the pending connection queue, ignored for pre-bound listeners.
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.config.backlog = backlog;
+        self
+    }
+
+    /// Throttle requests of every peer IP across all routes to `limit`, replacing
+    /// the global rate limiter of the router.
+    pub fn rate_limit(mut self, limit: Option<RateLimit>) -> Self {
+        self.config.rate_limit = limit;
+        self
+    }
+
+    pub fn build(mut self) -> Server {
+        if let Some(limit) = self.config.rate_limit {
+            self.router.rate_limit(RateLimiter::new(limit));
+        }
+        Server {
+            address: self.address,
+            #[cfg(unix)]
+            unix: self.unix,
+            listener: self.listener,
+            router: Arc::new(self.router),
+            config: Arc::new(self.config),
+        }
     }
 }
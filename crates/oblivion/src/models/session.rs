@@ -1,12 +1,19 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-use arc_swap::ArcSwap;
+use arc_swap::ArcSwapOption;
 use chrono::{DateTime, Local};
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 
 use ring::agreement::{EphemeralPrivateKey, PublicKey, UnparsedPublicKey, X25519};
+use tokio::sync::{watch, Mutex};
 use tokio::task::JoinHandle;
+use tracing::{debug, trace_span, warn, Instrument};
 
 use crate::exceptions::Exception;
 use crate::types::Callback;
@@ -14,9 +21,42 @@ use crate::utils::gear::Socket;
 use crate::utils::generator::generate_key_pair;
 use crate::utils::parser::{length, OblivionRequest};
 
-use super::client::Response;
-use super::packet::{OED, OKE, OSC};
-use super::render::BaseResponse;
+use super::body::{BodyReader, BodyWriter};
+use super::client::{Request, Response};
+#[cfg(feature = "serde")]
+use super::codec::Codec;
+use super::compression::{Compression, ENCODING_FLAG, ENCODING_MASK};
+use super::config::{CompressionConfig, HeartbeatConfig};
+use super::heartbeat::{Heartbeat, PING_FLAG, PONG_FLAG};
+use super::packet::{message, OED, OKE, OSC};
+use super::render::{BaseResponse, ResponseHead, HEAD_FLAG};
+
+/// Flag of a close frame, carrying `code reason` like an error frame.
+pub(crate) const CLOSE_FLAG: u32 = 12;
+
+/// Close code of a session closed normally, as sent by [`Session::close`].
+pub const NORMAL_CLOSURE: u32 = 1000;
+
+/// Which sides of a session stopped sending, it is closed once both did.
+#[derive(Debug, Clone, Default)]
+struct CloseState {
+    local_closed: bool,
+    remote_closed: bool,
+    /// Code and reason of the close frame from the peer.
+    reason: Option<(u32, String)>,
+}
+
+impl CloseState {
+    fn closed(&self) -> bool {
+        self.local_closed && self.remote_closed
+    }
+
+    /// Both sides are done, the connection ended or was given up on.
+    fn end(&mut self) {
+        self.local_closed = true;
+        self.remote_closed = true;
+    }
+}
 
 /// Oblivion Full Duplex Session
 ///
@@ -30,8 +70,17 @@ pub struct Session {
     pub request_time: DateTime<Local>,
     pub request: OblivionRequest,
     pub socket: Arc<Socket>,
-    closed: ArcSwap<bool>,
+    closed: Arc<watch::Sender<CloseState>>,
     callback: Arc<Option<Callback>>,
+    max_message_size: usize,
+    identity: Arc<ArcSwapOption<String>>,
+    reusable: AtomicBool,
+    reading: Arc<Mutex<()>>,
+    initiator: bool,
+    bodies_sent: Arc<AtomicU64>,
+    bodies_received: Arc<AtomicU64>,
+    compression: Arc<Compression>,
+    heartbeat: Arc<Heartbeat>,
 }
 
 impl Session {
@@ -45,8 +94,17 @@ impl Session {
             request_time: Local::now(),
             request: Default::default(),
             socket: Arc::new(socket),
-            closed: ArcSwap::new(Arc::new(false)),
+            closed: Arc::new(watch::Sender::new(CloseState::default())),
             callback: Arc::new(None),
+            max_message_size: usize::MAX,
+            identity: Arc::new(ArcSwapOption::empty()),
+            reusable: AtomicBool::new(false),
+            reading: Arc::new(Mutex::new(())),
+            initiator: false,
+            bodies_sent: Arc::new(AtomicU64::new(0)),
+            bodies_received: Arc::new(AtomicU64::new(0)),
+            compression: Arc::new(Compression::new(None)),
+            heartbeat: Arc::new(Heartbeat::new()),
         })
     }
 
@@ -60,8 +118,17 @@ impl Session {
             request_time: Local::now(),
             request: Default::default(),
             socket: Arc::new(socket),
-            closed: ArcSwap::new(Arc::new(false)),
+            closed: Arc::new(watch::Sender::new(CloseState::default())),
             callback: Arc::new(None),
+            max_message_size: usize::MAX,
+            identity: Arc::new(ArcSwapOption::empty()),
+            reusable: AtomicBool::new(false),
+            reading: Arc::new(Mutex::new(())),
+            initiator: false,
+            bodies_sent: Arc::new(AtomicU64::new(0)),
+            bodies_received: Arc::new(AtomicU64::new(0)),
+            compression: Arc::new(Compression::new(None)),
+            heartbeat: Arc::new(Heartbeat::new()),
         })
     }
 
@@ -69,64 +136,80 @@ impl Session {
     async fn first_hand(&mut self) -> Result<()> {
         let socket = Arc::clone(&self.socket);
         let header = self.header.as_bytes();
-        #[cfg(feature = "perf")]
-        let now = tokio::time::Instant::now();
-        socket.send(&length(header)?).await?;
-        socket.send(header).await?;
-        #[cfg(feature = "perf")]
-        println!("发送头时长: {}μs", now.elapsed().as_micros().to_string());
+        async {
+            socket.send(&length(header)?).await?;
+            socket.send(header).await
+        }
+        .instrument(trace_span!("send_header"))
+        .await?;
 
         let public_key = UnparsedPublicKey::new(&X25519, self.public_key.as_ref().to_vec());
         let mut oke = OKE::new(self.private_key.take(), public_key);
-        oke.from_stream_with_salt(&socket).await?;
+        async {
+            oke.from_stream_with_salt(&socket).await?;
+            oke.to_stream(&socket).await
+        }
+        .instrument(trace_span!("key_exchange"))
+        .await?;
         self.aes_key = oke.get_aes_key();
-        oke.to_stream(&socket).await?;
+        self.initiator = true;
         Ok(())
     }
 
     #[inline]
     async fn second_hand(&mut self) -> Result<()> {
-        #[cfg(feature = "perf")]
-        let now = tokio::time::Instant::now();
-        #[cfg(feature = "perf")]
-        use colored::Colorize;
         let socket = Arc::clone(&self.socket);
-        let peer = socket.peer_addr().await?;
-        #[cfg(feature = "perf")]
-        println!(
-            "开始入站时长: {}μs",
-            now.elapsed().as_micros().to_string().bright_magenta()
-        );
-        let len_header = socket.recv_usize().await?;
-        #[cfg(feature = "perf")]
-        println!(
-            "捕获头长度时长: {}μs",
-            now.elapsed().as_micros().to_string().bright_magenta()
-        );
-        let header = socket.recv_str(len_header).await?;
-        #[cfg(feature = "perf")]
-        println!(
-            "入站时长: {}μs",
-            now.elapsed().as_micros().to_string().bright_magenta()
-        );
+        let header = async {
+            let len_header = socket.recv_usize().await?;
+            if len_header > 2048 {
+                return Err(Exception::DataTooLarge { size: len_header }.into());
+            }
+            socket.recv_str(len_header).await
+        }
+        .instrument(trace_span!("recv_header"))
+        .await?;
         let mut request = OblivionRequest::new(&header)?;
-        request.set_remote_peer(&peer);
+        if let Some(peer) = socket.peer() {
+            request.set_remote_peer(&peer);
+        }
 
-        #[cfg(feature = "perf")]
-        let now = std::time::Instant::now();
         let public_key = UnparsedPublicKey::new(&X25519, self.public_key.as_ref().to_vec());
         let mut oke = OKE::new(self.private_key.take(), public_key);
-        oke.to_stream_with_salt(&socket).await?;
-        oke.from_stream(&socket).await?;
-        #[cfg(feature = "perf")]
-        println!(
-            "密钥交互时长: {}μs",
-            now.elapsed().as_micros().to_string().bright_magenta()
-        );
+        async {
+            oke.to_stream_with_salt(&socket).await?;
+            oke.from_stream(&socket).await?;
+            Ok::<(), anyhow::Error>(())
+        }
+        .instrument(trace_span!("key_exchange"))
+        .await?;
 
         request.aes_key = Some(oke.get_aes_key());
         self.aes_key = oke.get_aes_key();
 
+        // Headers and a body follow the key exchange of `POST` requests.
+        if request.method == "POST" {
+            let (flag, data) = self.read_message(None).await?;
+            if flag != 4 {
+                return Err(Exception::InvalidHeader(format!("unexpected frame {}", flag)).into());
+            }
+            let (entrance, sent) = Request::decode(data)?;
+            if entrance != request.entrance {
+                return Err(Exception::InvalidHeader(entrance).into());
+            }
+            request.headers = sent.headers;
+            request.body = sent.body;
+        }
+
+        // Clients offering compression are told the encoding picked, if any, before anything else.
+        if let Some(accepted) = request.get_header("accept-encoding") {
+            let encoding = self.compression.negotiate(accepted);
+            self.send_frame(
+                ENCODING_FLAG,
+                encoding.map_or("", |encoding| encoding.name()).into(),
+            )
+            .await?;
+        }
+
         self.request = request;
         self.header = header;
         Ok(())
@@ -142,46 +225,554 @@ impl Session {
     }
 
     pub async fn send(&self, data: Vec<u8>) -> Result<()> {
-        if self.closed().await {
+        if self.local_closed() {
             return Err(Exception::ConnectionClosed.into());
         }
 
-        let socket = &self.socket;
+        self.send_frame(0, data).await
+    }
 
-        OSC::from_u32(0).to_stream(socket).await?;
-        OED::new(&self.aes_key)
-            .from_bytes(data)?
-            .to_stream(socket)
-            .await?;
-        Ok(())
+    /// Write a whole message with `flag` at once.
+    pub(crate) async fn send_frame(&self, flag: u32, data: Vec<u8>) -> Result<()> {
+        self.send_sealed(flag, data, &[]).await
+    }
+
+    /// Write a whole message with `flag`, sealed along with the associated data `aad`.
+    pub(crate) async fn send_sealed(&self, flag: u32, data: Vec<u8>, aad: &[u8]) -> Result<()> {
+        self.socket.send(&self.seal(flag, data, aad)?).await
+    }
+
+    /// Send `data` without compressing it, whatever was negotiated.
+    ///
+    /// Meant for messages holding a secret along with data an attacker may choose,
+    /// whose compressed size would leak the secret, see [`compression`](super::compression).
+    pub async fn send_uncompressed(&self, data: Vec<u8>) -> Result<()> {
+        if self.local_closed() {
+            return Err(Exception::ConnectionClosed.into());
+        }
+
+        self.socket
+            .send(&message(0, &self.aes_key, data, &[])?)
+            .await
+    }
+
+    /// Serialize a message with `flag`, compressed if an encoding was negotiated and
+    /// sealed along with the associated data `aad`.
+    pub(crate) fn seal(&self, flag: u32, data: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>> {
+        let (flag, data) = self.compression.compress(flag, data)?;
+        message(flag, &self.aes_key, data, aad)
+    }
+
+    /// Read the next message, returning its flag and its content decompressed.
+    ///
+    /// The content of a message with the flag of `sealed` is opened along with its
+    /// associated data. Pings are answered and pongs recorded on the way, reads fail
+    /// once the heartbeat gives up on the peer and with `SessionClosed` on the close
+    /// frame of the peer. Callers hold the reading lock.
+    async fn read_message(&self, sealed: Option<(u32, &[u8])>) -> Result<(u32, Vec<u8>)> {
+        let _reading = self.heartbeat.reading();
+        self.next_message(sealed).await
+    }
+
+    /// Read the next message like [`Session::read_message`], without pinging the peer
+    /// meanwhile.
+    async fn next_message(&self, sealed: Option<(u32, &[u8])>) -> Result<(u32, Vec<u8>)> {
+        loop {
+            let (flag, content) = tokio::select! {
+                received = self.read_frame(sealed) => match received {
+                    Ok(received) => received,
+                    Err(error) => {
+                        if disconnected(&error) {
+                            self.closed.send_modify(CloseState::end);
+                        }
+                        return Err(error);
+                    }
+                },
+                _ = self.heartbeat.expired() => {
+                    return Err(Exception::HeartbeatTimeout {
+                        missed: self.heartbeat.unanswered(),
+                    }
+                    .into())
+                }
+            };
+            self.heartbeat.alive();
+            match flag {
+                PING_FLAG => {
+                    if let Err(error) = self.send_frame(PONG_FLAG, content).await {
+                        debug!(%error, "failed to answer a ping");
+                    }
+                }
+                PONG_FLAG => self.heartbeat.pong(&content),
+                CLOSE_FLAG => {
+                    let (code, reason) = code_message(&content);
+                    self.closed.send_modify(|state| {
+                        state.remote_closed = true;
+                        state.reason = Some((code, reason.clone()));
+                    });
+                    // The connection ends once both sides sent their close frame.
+                    if self.closed().await {
+                        let _ = self.socket.close().await;
+                    }
+                    return Err(Exception::SessionClosed { code, reason }.into());
+                }
+                _ => return Ok((flag, content)),
+            }
+        }
+    }
+
+    async fn read_frame(&self, sealed: Option<(u32, &[u8])>) -> Result<(u32, Vec<u8>)> {
+        let received = OSC::from_stream(&self.socket).await?.status_code;
+        self.read_content(received, sealed).await
+    }
+
+    /// Read the content of a message with the flag `received`, read already.
+    async fn read_content(
+        &self,
+        received: u32,
+        sealed: Option<(u32, &[u8])>,
+    ) -> Result<(u32, Vec<u8>)> {
+        let mut oed = OED::new(&self.aes_key);
+        oed.limit(self.max_message_size);
+        if let Some((flag, aad)) = sealed {
+            if received & !ENCODING_MASK == flag {
+                oed.associate(aad);
+            }
+        }
+        let content = oed.from_stream(&self.socket).await?.take();
+        self.compression
+            .decompress(received, content, self.max_message_size)
+    }
+
+    /// Send a ping to the peer, whose pong updates [`Session::rtt`] once read.
+    ///
+    /// ```rust
+    /// use oblivion::models::client::Client;
+    /// use oblivion::models::render::BaseResponse;
+    /// use oblivion::models::router::{RoutePath, RouteType, Router};
+    /// use oblivion::models::server::Server;
+    /// use oblivion::models::session::Session;
+    /// use oblivion::types::ServerResponse;
+    /// use oblivion_codegen::async_route;
+    /// use tokio::net::TcpListener;
+    ///
+    /// #[async_route]
+    /// async fn echo(session: Session) -> ServerResponse {
+    ///     Ok(BaseResponse::BytesResponse(session.recv().await?.content, None))
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let mut router = Router::new();
+    /// router.route(RoutePath::new("/echo", RouteType::Path), echo);
+    /// let listener = TcpListener::bind("127.0.0.1:0").await?;
+    /// let server = Server::builder(router).listener(listener).build();
+    /// let address = server.local_addr().unwrap();
+    /// tokio::spawn(async move { server.run().await });
+    ///
+    /// let client = Client::connect(&format!("{}/echo", address)).await?;
+    /// client.session.ping().await?;
+    /// client.send(b"hello".to_vec()).await?;
+    /// assert_eq!(client.recv().await?.content, b"hello");
+    /// assert!(client.session.rtt().is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn ping(&self) -> Result<()> {
+        if self.local_closed() {
+            return Err(Exception::ConnectionClosed.into());
+        }
+
+        self.send_frame(PING_FLAG, self.heartbeat.ping()).await
+    }
+
+    /// Round-trip time measured by the last pong answering a ping, `None` until one is read.
+    #[inline]
+    pub fn rtt(&self) -> Option<Duration> {
+        self.heartbeat.rtt()
+    }
+
+    /// Ping the peer on the interval of `config` until the session is closed, closing
+    /// it once the peer leaves too many pings in a row unanswered.
+    ///
+    /// Pongs are only noticed while the session is being read, as [`Session::listen`]
+    /// does, pings are only sent then. A side that only writes is never given up on.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use oblivion::models::client::Client;
+    /// use oblivion::models::config::HeartbeatConfig;
+    /// use oblivion::models::render::BaseResponse;
+    /// use oblivion::models::router::{RoutePath, RouteType, Router};
+    /// use oblivion::models::server::Server;
+    /// use oblivion::models::session::Session;
+    /// use oblivion::types::ServerResponse;
+    /// use oblivion_codegen::async_route;
+    /// use tokio::net::TcpListener;
+    ///
+    /// #[async_route]
+    /// async fn ticks(session: Session) -> ServerResponse {
+    ///     for tick in 0..10u8 {
+    ///         session.send(vec![tick]).await?;
+    ///         tokio::time::sleep(Duration::from_millis(50)).await;
+    ///     }
+    ///     Ok(BaseResponse::TextResponse("done".to_string()))
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let mut router = Router::new();
+    /// router.route(RoutePath::new("/ticks", RouteType::Path), ticks);
+    /// let listener = TcpListener::bind("127.0.0.1:0").await?;
+    /// let heartbeat = HeartbeatConfig {
+    ///     interval: Duration::from_millis(20),
+    ///     max_missed: 2,
+    /// };
+    /// let server = Server::builder(router)
+    ///     .listener(listener)
+    ///     .heartbeat(Some(heartbeat))
+    ///     .build();
+    /// let address = server.local_addr().unwrap();
+    /// tokio::spawn(async move { server.run().await });
+    ///
+    /// // The handler never reads, the pongs of the client cannot be missed.
+    /// let client = Client::connect(&format!("{}/ticks", address)).await?;
+    /// for tick in 0..10u8 {
+    ///     assert_eq!(client.recv().await?.content, [tick]);
+    /// }
+    /// assert_eq!(client.recv().await?.text()?, "done");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn start_heartbeat(&self, config: HeartbeatConfig) -> Result<JoinHandle<()>> {
+        config.validate()?;
+        let socket = Arc::downgrade(&self.socket);
+        let closed = Arc::clone(&self.closed);
+        let heartbeat = Arc::clone(&self.heartbeat);
+        let aes_key = self.aes_key;
+        Ok(tokio::spawn(async move {
+            let start = tokio::time::Instant::now() + config.interval;
+            let mut interval = tokio::time::interval_at(start, config.interval);
+            loop {
+                interval.tick().await;
+                let Some(socket) = socket.upgrade().filter(|_| !closed.borrow().closed()) else {
+                    break;
+                };
+                let missed = heartbeat.unanswered();
+                if !heartbeat.watched() {
+                    // Pongs may be waiting unread, the pings sent so far prove nothing.
+                    heartbeat.alive();
+                } else if missed >= config.max_missed {
+                    warn!(missed, "peer stopped answering pings, closing the session");
+                    closed.send_modify(CloseState::end);
+                    heartbeat.expire();
+                    let _ = socket.close().await;
+                    break;
+                }
+                if !heartbeat.is_read() {
+                    continue;
+                }
+                let ping = message(PING_FLAG, &aes_key, heartbeat.ping(), &[]);
+                if let Err(error) = async { socket.send(&ping?).await }.await {
+                    debug!(%error, "failed to send a ping");
+                    break;
+                }
+            }
+        }))
+    }
+
+    /// Whether the session is still open with nothing from the peer waiting to be
+    /// read, once the pings and pongs it sent meanwhile are handled.
+    ///
+    /// Only meaningful between exchanges, as checked before reusing a pooled session.
+    pub(crate) async fn is_idle(&self) -> bool {
+        let Ok(_reading) = self.reading.try_lock() else {
+            return false;
+        };
+        if self.local_closed() || self.remote_closed() {
+            return false;
+        }
+        loop {
+            let received = match self.socket.try_recv_u32().await {
+                Ok(Some(received)) => received,
+                Ok(None) => return true,
+                Err(_) => return false,
+            };
+            // The rest of a message is sent along with its flag.
+            let content =
+                tokio::time::timeout(Duration::from_secs(1), self.read_content(received, None))
+                    .await;
+            match content {
+                Ok(Ok((PING_FLAG, content))) => {
+                    if self.send_frame(PONG_FLAG, content).await.is_err() {
+                        return false;
+                    }
+                }
+                Ok(Ok((PONG_FLAG, content))) => self.heartbeat.pong(&content),
+                _ => return false,
+            }
+        }
+    }
+
+    /// Read the encoding the server picked among the ones offered in the request.
+    pub(crate) async fn recv_encoding(&self) -> Result<()> {
+        let reading = self.reading.lock().await;
+        let (flag, content) = self.read_message(None).await?;
+        drop(reading);
+
+        match flag {
+            ENCODING_FLAG => self.compression.announced(&content),
+            2 => {
+                self.shutdown().await?;
+                Err(server_error(&content).into())
+            }
+            _ => Err(Exception::InvalidHeader(format!("unexpected frame {}", flag)).into()),
+        }
+    }
+
+    /// Read the next message, which must have `flag` and be sealed along with `aad`.
+    ///
+    /// Any other message is reported as `BodyTruncated`, except for error frames.
+    pub(crate) async fn recv_sealed(&self, flag: u32, aad: &[u8]) -> Result<Vec<u8>> {
+        if self.remote_closed() {
+            return Err(Exception::ConnectionClosed.into());
+        }
+
+        let reading = self.reading.lock().await;
+        let (received, content) = self.read_message(Some((flag, aad))).await?;
+        drop(reading);
+
+        match received {
+            _ if received == flag => Ok(content),
+            2 => {
+                self.shutdown().await?;
+                Err(server_error(&content).into())
+            }
+            _ => Err(Exception::BodyTruncated.into()),
+        }
+    }
+
+    /// Start sending a body in chunks, see [`BodyWriter`].
+    pub fn send_body(&self) -> BodyWriter<'_> {
+        BodyWriter::new(self, self.bodies_sent.fetch_add(1, Ordering::AcqRel))
+    }
+
+    /// Start receiving the next body sent by the peer, see [`BodyReader`].
+    pub fn recv_body(&self) -> BodyReader<'_> {
+        BodyReader::new(self, self.bodies_received.fetch_add(1, Ordering::AcqRel))
+    }
+
+    /// Side of the session, authenticated along with the chunks of a body.
+    #[inline]
+    pub(crate) fn side(&self, local: bool) -> u8 {
+        match self.initiator == local {
+            true => b'C',
+            false => b'S',
+        }
     }
 
     pub async fn send_json(&self, json: Value) -> Result<()> {
         self.send(json.to_string().into_bytes()).await
     }
 
+    /// Send `value` serialized as JSON.
+    #[cfg(feature = "serde")]
+    pub async fn send_as<T: Serialize + ?Sized>(&self, value: &T) -> Result<()> {
+        self.send(serde_json::to_vec(value)?).await
+    }
+
+    /// Send `value` encoded with `codec`, tagged with the content type of the codec so
+    /// that [`Session::recv_as`] decodes it the same way.
+    #[cfg(feature = "serde")]
+    pub async fn send_with<T: Serialize + ?Sized>(&self, codec: Codec, value: &T) -> Result<()> {
+        self.response(BaseResponse::encode(codec, value)?).await
+    }
+
     pub async fn response(&self, response: BaseResponse) -> Result<()> {
-        self.send(response.as_bytes()?)
-            .await
+        match response.body() {
+            BaseResponse::FileResponse(file) => self.send_file(file).await.map(|_| ()),
+            _ => {
+                if self.local_closed() {
+                    return Err(Exception::ConnectionClosed.into());
+                }
+                self.socket.send(&response.to_messages(0, self)?).await
+            }
+        }
+    }
+
+    /// Send an error frame with `code` and `message` to the peer and close the session
+    /// without waiting for the peer.
+    pub async fn error(&self, code: u32, message: &str) -> Result<()> {
+        if self.local_closed() {
+            return Err(Exception::ConnectionClosed.into());
+        }
+
+        self.send_frame(2, format!("{} {}", code, message).into_bytes())
+            .await?;
+        self.shutdown().await
     }
 
     pub async fn recv(&self) -> Result<Response> {
-        if self.closed().await {
+        if self.remote_closed() {
             return Err(Exception::ConnectionClosed.into());
         }
 
-        let socket = &self.socket;
+        // Concurrent readers take turns message by message, the head of a response
+        // and its content are read together.
+        let reading = self.reading.lock().await;
+        let mut head = ResponseHead::default();
+        let (flag, content) = loop {
+            let (flag, content) = self.read_message(None).await?;
+            match flag {
+                HEAD_FLAG => head = ResponseHead::from_json(&serde_json::from_slice(&content)?)?,
+                _ => break (flag, content),
+            }
+        };
+        drop(reading);
+
+        if flag == 2 {
+            self.shutdown().await?;
+            return Err(server_error(&content).into());
+        }
 
-        let flag = OSC::from_stream(socket).await?.status_code;
-        let content = OED::new(&self.aes_key).from_stream(socket).await?.take();
-        let response = Response::new(None, content, None, flag);
+        let mut response = Response::new(
+            head.headers.get("content-type").cloned(),
+            content,
+            None,
+            flag,
+        );
+        response.status = head.status;
+        response.headers = head.headers;
 
-        if flag == 1 {
-            socket.close().await?;
+        match flag {
+            1 => self.shutdown().await?,
+            3 => self.reusable.store(true, Ordering::Release),
+            _ => {}
         }
         Ok(response)
     }
 
+    /// Request another `entrance` with `request`, either a [`Request`] or a body, on
+    /// this session once the previous response was received.
+    ///
+    /// Only servers keeping sessions open end their responses with flag `3`, any other
+    /// session fails with `ConnectionClosed`.
+    pub async fn request(&self, entrance: &str, request: impl Into<Request>) -> Result<()> {
+        if entrance.contains(char::is_whitespace) {
+            return Err(Exception::InvalidOblivion {
+                entrance: entrance.to_string(),
+            }
+            .into());
+        }
+        if self.local_closed() || !self.reusable.swap(false, Ordering::AcqRel) {
+            return Err(Exception::ConnectionClosed.into());
+        }
+
+        self.send_frame(4, request.into().encode(entrance)).await
+    }
+
+    /// Whether the server kept the session open after its last response, so that
+    /// another entrance may be requested.
+    pub fn reusable(&self) -> bool {
+        !self.local_closed() && !self.remote_closed() && self.reusable.load(Ordering::Acquire)
+    }
+
+    /// Session serving the next request on the same connection, sharing its keys and state.
+    pub(crate) fn renew(&self) -> Self {
+        Self {
+            header: self.header.clone(),
+            private_key: None,
+            public_key: self.public_key.clone(),
+            aes_key: self.aes_key,
+            request_time: self.request_time,
+            request: self.request.clone(),
+            socket: Arc::clone(&self.socket),
+            closed: Arc::clone(&self.closed),
+            callback: Arc::new(None),
+            max_message_size: self.max_message_size,
+            identity: Arc::clone(&self.identity),
+            reusable: AtomicBool::new(false),
+            reading: Arc::clone(&self.reading),
+            initiator: self.initiator,
+            bodies_sent: Arc::clone(&self.bodies_sent),
+            bodies_received: Arc::clone(&self.bodies_received),
+            compression: Arc::clone(&self.compression),
+            heartbeat: Arc::clone(&self.heartbeat),
+        }
+    }
+
+    /// Wait up to `timeout` for the peer to request the next entrance on this session.
+    ///
+    /// Returns `false` once the peer closes the connection or leaves it idle past
+    /// `timeout` or the read timeout instead.
+    pub(crate) async fn accept_request(&mut self, timeout: Option<Duration>) -> Result<bool> {
+        let socket = Arc::clone(&self.socket);
+        let reading = Arc::clone(&self.reading);
+        let _reading = reading.lock().await;
+
+        // Sessions awaiting a request are not pinged, idle pooled clients do not read
+        // and the persistent timeout ends the ones left idle too long.
+        let received = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.next_message(None)).await {
+                Ok(received) => received,
+                Err(_) => return Ok(false),
+            },
+            None => self.next_message(None).await,
+        };
+        let (flag, data) = match received {
+            Ok(received) => received,
+            Err(error) if ended(&error) => {
+                // Answer the close frame of the client, if that is what ended it.
+                if self.remote_closed() {
+                    let _ = self.close().await;
+                }
+                return Ok(false);
+            }
+            Err(error) => return Err(error),
+        };
+        if flag != 4 {
+            return Err(Exception::InvalidHeader(format!("unexpected frame {}", flag)).into());
+        }
+        let (entrance, sent) = Request::decode(data)?;
+        if !entrance.starts_with('/') || entrance.contains(char::is_whitespace) {
+            return Err(Exception::InvalidHeader(entrance.to_string()).into());
+        }
+
+        let header = format!(
+            "{} {} {}/{}",
+            if sent.is_empty() { "CONNECT" } else { "POST" },
+            entrance,
+            self.request.protocol,
+            self.request.get_version()
+        );
+        let mut request = OblivionRequest::new(&header)?;
+        if let Some(peer) = socket.peer() {
+            request.set_remote_peer(&peer);
+        }
+        request.aes_key = Some(self.aes_key);
+        request.headers = sent.headers;
+        request.body = sent.body;
+
+        self.request = request;
+        self.header = header;
+        self.request_time = Local::now();
+        Ok(true)
+    }
+
+    /// Compress messages as configured once an encoding is negotiated with the peer.
+    pub fn set_compression(&mut self, config: Option<CompressionConfig>) {
+        self.compression = Arc::new(Compression::new(config));
+    }
+
+    /// Value of the `accept-encoding` header offering the configured compression.
+    pub(crate) fn accepted_encodings(&self) -> Option<String> {
+        self.compression.accepted()
+    }
+
+    /// Reject messages from the peer larger than `size` bytes once encrypted.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
     pub fn set_callback(&mut self, callback: Callback) {
         self.callback = Arc::new(Some(callback));
     }
@@ -190,7 +781,15 @@ impl Session {
         let callback = Arc::clone(&self.callback);
         let future = tokio::spawn(async move {
             while !self.closed().await {
-                let response = self.recv().await.unwrap();
+                let response = match self.recv().await {
+                    Ok(response) => response,
+                    Err(error) => {
+                        debug!(%error, "session ended");
+                        // Answer the close frame of the peer, if that is what ended it.
+                        let _ = self.close().await;
+                        break;
+                    }
+                };
                 if let Some(callback) = &*callback {
                     if !callback(response, self.clone()).await {
                         break;
@@ -202,22 +801,167 @@ impl Session {
     }
 
     pub async fn recv_json(&self) -> Result<Value> {
-        let response = self.recv().await?;
-        Ok(serde_json::from_slice(&response.content)?)
+        self.recv().await?.json()
+    }
+
+    /// Receive the next message and deserialize it with the codec of its content
+    /// type, JSON unless it was sent with [`Session::send_with`].
+    ///
+    /// Content that is not a valid `T` fails with `Exception::DeserializeError`, handlers
+    /// returning any `Serialize` type with `async_route` are received this way.
+    ///
+    /// ```rust
+    /// use oblivion::models::client::Client;
+    /// use oblivion::models::router::{RoutePath, RouteType, Router};
+    /// use oblivion::models::server::Server;
+    /// use oblivion::models::session::Session;
+    /// use oblivion_codegen::async_route;
+    /// use serde::{Deserialize, Serialize};
+    /// use tokio::net::TcpListener;
+    ///
+    /// #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    /// struct Point {
+    ///     x: i32,
+    ///     y: i32,
+    /// }
+    ///
+    /// #[async_route]
+    /// async fn mirror(session: Session) -> anyhow::Result<Point> {
+    ///     let point: Point = session.recv_as().await?;
+    ///     Ok(Point { x: -point.x, y: -point.y })
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let mut router = Router::new();
+    /// router.route(RoutePath::new("/mirror", RouteType::Path), mirror);
+    /// let listener = TcpListener::bind("127.0.0.1:0").await?;
+    /// let server = Server::builder(router).listener(listener).build();
+    /// let address = server.local_addr().unwrap();
+    /// tokio::spawn(async move { server.run().await });
+    ///
+    /// let client = Client::connect(&format!("{}/mirror", address)).await?;
+    /// client.send_as(&Point { x: 1, y: 2 }).await?;
+    /// assert_eq!(client.recv_as::<Point>().await?, Point { x: -1, y: -2 });
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "serde")]
+    pub async fn recv_as<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(self.recv().await?.decode()?)
     }
 
+    /// Close the session normally, see [`Session::close_with`].
     pub async fn close(&self) -> Result<()> {
-        if !self.closed().await {
-            self.closed.store(Arc::new(true));
-            self.socket.close().await
-        } else {
-            Ok(())
+        self.close_with(NORMAL_CLOSURE, "").await
+    }
+
+    /// Send a close frame with `code` and `reason` and stop sending.
+    ///
+    /// The peer may keep sending until it closes its side too, messages it sent before
+    /// are still received and the first read past its close frame fails with
+    /// `SessionClosed`. Closing a session the peer already closed completes it.
+    ///
+    /// ```rust
+    /// use oblivion::exceptions::Exception;
+    /// use oblivion::models::client::Client;
+    /// use oblivion::models::render::BaseResponse;
+    /// use oblivion::models::router::{RoutePath, RouteType, Router};
+    /// use oblivion::models::server::Server;
+    /// use oblivion::models::session::Session;
+    /// use oblivion::types::ServerResponse;
+    /// use oblivion_codegen::async_route;
+    /// use tokio::net::TcpListener;
+    ///
+    /// #[async_route]
+    /// async fn farewell(session: Session) -> ServerResponse {
+    ///     session.send(b"last words".to_vec()).await?;
+    ///     session.close_with(4000, "done").await?;
+    ///     assert!(session.recv().await.is_err());
+    ///     assert!(session.closed().await);
+    ///     Ok(BaseResponse::TextResponse(String::new()))
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let mut router = Router::new();
+    /// router.route(RoutePath::new("/farewell", RouteType::Path), farewell);
+    /// let listener = TcpListener::bind("127.0.0.1:0").await?;
+    /// let server = Server::builder(router).listener(listener).build();
+    /// let address = server.local_addr().unwrap();
+    /// tokio::spawn(async move { server.run().await });
+    ///
+    /// let client = Client::connect(&format!("{}/farewell", address)).await?;
+    /// assert_eq!(client.recv().await?.content, b"last words");
+    /// let error = client.recv().await.unwrap_err();
+    /// assert_eq!(
+    ///     error.downcast_ref::<Exception>(),
+    ///     Some(&Exception::SessionClosed { code: 4000, reason: "done".to_string() })
+    /// );
+    /// assert!(!client.session.closed().await);
+    /// client.close().await?;
+    /// assert!(client.session.closed().await);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn close_with(&self, code: u32, reason: &str) -> Result<()> {
+        let closing = self.closed.send_if_modified(|state| {
+            let closing = !state.local_closed;
+            state.local_closed = true;
+            closing
+        });
+        if !closing {
+            return Ok(());
+        }
+
+        let sent = async {
+            self.send_frame(CLOSE_FLAG, format!("{} {}", code, reason).into_bytes())
+                .await?;
+            match self.closed().await {
+                true => self.socket.close().await,
+                false => Ok(()),
+            }
+        }
+        .await;
+        if sent.is_err() {
+            self.closed.send_modify(CloseState::end);
         }
+        sent
+    }
+
+    /// Stop both ways at once without a close frame, after a terminal frame.
+    async fn shutdown(&self) -> Result<()> {
+        self.closed.send_modify(CloseState::end);
+        self.socket.close().await
     }
 
+    /// Whether both sides stopped sending, either with close frames or because the
+    /// connection ended.
     #[inline]
     pub async fn closed(&self) -> bool {
-        **self.closed.load()
+        self.closed.borrow().closed()
+    }
+
+    /// Wait until the session is closed, which takes a read to notice the close
+    /// frame of the peer.
+    pub async fn wait_closed(&self) {
+        let _ = self.closed.subscribe().wait_for(CloseState::closed).await;
+    }
+
+    /// Code and reason of the close frame received from the peer, if any.
+    pub fn close_reason(&self) -> Option<(u32, String)> {
+        self.closed.borrow().reason.clone()
+    }
+
+    /// Whether this side stopped sending.
+    #[inline]
+    pub(crate) fn local_closed(&self) -> bool {
+        self.closed.borrow().local_closed
+    }
+
+    #[inline]
+    fn remote_closed(&self) -> bool {
+        self.closed.borrow().remote_closed
     }
 
     #[inline]
@@ -225,8 +969,56 @@ impl Session {
         &self.header
     }
 
+    /// Record the identity the peer authenticated as, reported in the access log.
+    pub fn set_identity(&self, identity: &str) {
+        self.identity.store(Some(Arc::new(identity.to_string())));
+    }
+
+    #[inline]
+    pub fn identity(&self) -> Option<Arc<String>> {
+        self.identity.load_full()
+    }
+
+    /// Shared slot of the identity, still readable once the session is consumed.
+    pub(crate) fn identity_slot(&self) -> Arc<ArcSwapOption<String>> {
+        Arc::clone(&self.identity)
+    }
+
     #[inline]
     pub fn get_ip(&self) -> &str {
         self.request.get_ip()
     }
 }
+
+/// Parse the `code message` content of an error or close frame.
+fn code_message(content: &[u8]) -> (u32, String) {
+    let content = String::from_utf8_lossy(content);
+    let (code, message) = content.split_once(' ').unwrap_or((&content, ""));
+    (code.parse().unwrap_or(500), message.to_string())
+}
+
+fn server_error(content: &[u8]) -> Exception {
+    let (code, message) = code_message(content);
+    Exception::ServerError { code, message }
+}
+
+/// Whether `error` is the connection itself ending.
+fn disconnected(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<io::Error>().map(io::Error::kind),
+        Some(io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset)
+    )
+}
+
+/// Whether `error` is the peer going away rather than a broken exchange.
+fn ended(error: &anyhow::Error) -> bool {
+    disconnected(error)
+        || matches!(
+            error.downcast_ref::<Exception>(),
+            Some(
+                Exception::ReadTimeout { .. }
+                    | Exception::HeartbeatTimeout { .. }
+                    | Exception::SessionClosed { .. }
+            )
+        )
+}
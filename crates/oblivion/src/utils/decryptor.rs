@@ -15,13 +15,24 @@ pub fn decrypt_bytes(
     tag: &[u8],
     aes_key: &[u8],
     nonce: &[u8],
+) -> Result<Vec<u8>, Unspecified> {
+    decrypt_bytes_with_aad(cipher_bytes, tag, aes_key, nonce, &[])
+}
+
+/// Decrypts the given cipher bytes, which must have been encrypted along with `aad`.
+pub fn decrypt_bytes_with_aad(
+    cipher_bytes: Vec<u8>,
+    tag: &[u8],
+    aes_key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
 ) -> Result<Vec<u8>, Unspecified> {
     let unbound_key = UnboundKey::new(&AES_128_GCM, aes_key)?;
     let nonce_sequence = AbsoluteNonceSequence::new(nonce);
 
     let mut opening_key = OpeningKey::new(unbound_key, nonce_sequence);
     let mut in_out = [cipher_bytes, tag.to_vec()].concat(); // 复制一份
-    let decrypted_data = opening_key.open_in_place(Aad::empty(), &mut in_out)?;
+    let decrypted_data = opening_key.open_in_place(Aad::from(aad), &mut in_out)?;
 
     Ok(decrypted_data.to_vec())
 }
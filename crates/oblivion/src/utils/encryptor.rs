@@ -22,7 +22,16 @@ pub fn encrypt_plaintext(string: String, aes_key: &[u8]) -> Result<EncryptedData
 }
 
 /// Encrypt binary data using AES
-pub fn encrypt_bytes(mut bytes: Vec<u8>, aes_key: &[u8]) -> Result<EncryptedData, Exception> {
+pub fn encrypt_bytes(bytes: Vec<u8>, aes_key: &[u8]) -> Result<EncryptedData, Exception> {
+    encrypt_bytes_with_aad(bytes, aes_key, &[])
+}
+
+/// Encrypt binary data using AES, authenticating `aad` along with it
+pub fn encrypt_bytes_with_aad(
+    mut bytes: Vec<u8>,
+    aes_key: &[u8],
+    aad: &[u8],
+) -> Result<EncryptedData, Exception> {
     let unbound_key = match UnboundKey::new(&AES_128_GCM, aes_key) {
         Ok(key) => key,
         Err(error) => return Err(Exception::EncryptError { error }),
@@ -35,7 +44,7 @@ pub fn encrypt_bytes(mut bytes: Vec<u8>, aes_key: &[u8]) -> Result<EncryptedData
     let nonce_sequence = AbsoluteNonceSequence::new(&nonce_bytes);
     let mut sealing_key = SealingKey::new(unbound_key, nonce_sequence);
 
-    let associated_data = Aad::empty();
+    let associated_data = Aad::from(aad);
 
     let tag = match sealing_key.seal_in_place_separate_tag(associated_data, &mut bytes) {
         Ok(result) => result,
@@ -1,14 +1,24 @@
 //! Oblivion Abstract Gear
+use std::fmt;
+use std::io;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
 use anyhow::Result;
+use futures::FutureExt;
 use ring::aead::{Nonce, NonceSequence};
 use ring::error::Unspecified;
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::sync::Mutex;
+#[cfg(feature = "perf")]
+use tracing::Instrument;
+
+use crate::exceptions::Exception;
 
 /// Absolute Nonce Sequence Structure
 ///
@@ -32,66 +42,170 @@ impl<'a> AbsoluteNonceSequence<'a> {
     }
 }
 
+/// Read half of the transport stream of a [`Socket`].
+pub type OwnedReadHalf = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Write half of the transport stream of a [`Socket`].
+pub type OwnedWriteHalf = Box<dyn AsyncWrite + Send + Unpin>;
+
 /// Socket Abstract Structure
 ///
 /// Used to abstract Oblivion's handling of transmitted data, wrapping all data type conversions.
-#[derive(Debug)]
+///
+/// Any stream implementing `AsyncRead + AsyncWrite` can carry Oblivion, e.g. an
+/// in-memory pipe:
+///
+/// ```rust
+/// use oblivion::utils::gear::Socket;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let (client, server) = tokio::io::duplex(1024);
+/// let (client, server) = (Socket::from_stream(client, None), Socket::from_stream(server, None));
+///
+/// client.send(&42u32.to_be_bytes()).await?;
+/// assert_eq!(server.recv_u32().await?, 42);
+/// assert_eq!(server.bytes_read(), 4);
+/// # Ok(())
+/// # }
+/// ```
 pub struct Socket {
     pub reader: Mutex<OwnedReadHalf>,
     pub writer: Mutex<OwnedWriteHalf>,
+    peer: Option<SocketAddr>,
+    read_timeout: Option<Duration>,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
 }
 
 impl Socket {
     pub fn new(tcp: TcpStream) -> Self {
+        let peer = tcp.peer_addr().ok();
         let (reader, writer) = tcp.into_split();
+        Self::from_halves(Box::new(reader), Box::new(writer), peer)
+    }
+
+    /// Wrap a Unix domain socket stream, whose peer has no IP address.
+    #[cfg(unix)]
+    pub fn new_unix(stream: UnixStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self::from_halves(Box::new(reader), Box::new(writer), None)
+    }
+
+    /// Wrap any pre-connected stream, `peer` is the address of the other end if it has one.
+    pub fn from_stream<S>(stream: S, peer: Option<SocketAddr>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        Self::from_halves(Box::new(reader), Box::new(writer), peer)
+    }
+
+    /// Wrap separate read and write halves of a stream.
+    pub fn from_halves(
+        reader: OwnedReadHalf,
+        writer: OwnedWriteHalf,
+        peer: Option<SocketAddr>,
+    ) -> Self {
         Self {
             reader: Mutex::new(reader),
             writer: Mutex::new(writer),
+            peer,
+            read_timeout: None,
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
         }
     }
 
+    /// Take back the read and write halves of the stream.
+    pub fn into_halves(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        (self.reader.into_inner(), self.writer.into_inner())
+    }
+
+    /// Total bytes received from the peer so far.
+    #[inline]
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// Total bytes sent to the peer so far.
+    #[inline]
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    /// Limit how long a single read may wait for the peer, `None` waits forever.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    #[inline]
+    async fn read_exact(&self, reader: &mut OwnedReadHalf, buf: &mut [u8]) -> Result<()> {
+        match self.read_timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, reader.read_exact(buf)).await {
+                Ok(result) => {
+                    result?;
+                }
+                Err(_) => return Err(Exception::ReadTimeout { timeout }.into()),
+            },
+            None => {
+                reader.read_exact(buf).await?;
+            }
+        }
+        self.bytes_read
+            .fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Address of the peer, `None` for Unix domain sockets and other streams without one.
+    #[inline]
+    pub fn peer(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
     #[inline]
     pub async fn peer_addr(&self) -> Result<SocketAddr> {
-        Ok(self.writer.lock().await.peer_addr()?)
+        Ok(self
+            .peer
+            .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?)
     }
 
     #[inline]
     pub async fn recv_usize(&self) -> Result<usize> {
         let mut len_bytes = [0; 4];
         #[cfg(not(feature = "perf"))]
-        self.reader.lock().await.read_exact(&mut len_bytes).await?;
+        let mut reader = self.reader.lock().await;
         #[cfg(feature = "perf")]
-        {
-            use colored::Colorize;
-            let now = tokio::time::Instant::now();
-            let mut reader = self.reader.lock().await;
-            println!(
-                "夺锁时长: {}μs",
-                now.elapsed().as_micros().to_string().bright_magenta()
-            );
-            reader.read_exact(&mut len_bytes).await?;
-        }
+        let mut reader = self
+            .reader
+            .lock()
+            .instrument(tracing::trace_span!("reader_lock"))
+            .await;
+        self.read_exact(&mut reader, &mut len_bytes).await?;
         Ok(u32::from_be_bytes(len_bytes) as usize)
     }
 
     #[inline]
     pub async fn recv_u32(&self) -> Result<u32> {
         let mut len_bytes = [0; 4];
-        self.reader.lock().await.read_exact(&mut len_bytes).await?;
+        self.read_exact(&mut *self.reader.lock().await, &mut len_bytes)
+            .await?;
         Ok(u32::from_be_bytes(len_bytes))
     }
 
     #[inline]
     pub async fn recv(&self, len: usize) -> Result<Vec<u8>> {
         let mut recv_bytes: Vec<u8> = vec![0; len];
-        self.reader.lock().await.read_exact(&mut recv_bytes).await?;
+        self.read_exact(&mut *self.reader.lock().await, &mut recv_bytes)
+            .await?;
         Ok(recv_bytes)
     }
 
     #[inline]
     pub async fn recv_str(&self, len: usize) -> Result<String> {
         let mut recv_bytes: Vec<u8> = vec![0; len];
-        self.reader.lock().await.read_exact(&mut recv_bytes).await?;
+        self.read_exact(&mut *self.reader.lock().await, &mut recv_bytes)
+            .await?;
         Ok(String::from_utf8(recv_bytes)?)
     }
 
@@ -100,11 +214,45 @@ impl Socket {
         let mut writer = self.writer.lock().await;
         writer.write_all(data).await?;
         writer.flush().await?;
+        self.bytes_written
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
         Ok(())
     }
 
+    /// Read the next `u32` if the peer already started sending it, `None` while
+    /// nothing is pending.
+    pub(crate) async fn try_recv_u32(&self) -> Result<Option<u32>> {
+        let mut reader = self
+            .reader
+            .try_lock()
+            .map_err(|_| io::Error::from(io::ErrorKind::WouldBlock))?;
+        let mut bytes = [0; 4];
+        match reader.read(&mut bytes[..1]).now_or_never() {
+            None => return Ok(None),
+            Some(Ok(0)) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Some(read) => read?,
+        };
+        self.bytes_read.fetch_add(1, Ordering::Relaxed);
+        self.read_exact(&mut reader, &mut bytes[1..]).await?;
+        Ok(Some(u32::from_be_bytes(bytes)))
+    }
+
     pub async fn close(&self) -> Result<()> {
-        self.writer.lock().await.shutdown().await?;
-        Ok(())
+        match self.writer.lock().await.shutdown().await {
+            // The peer may already have reset the connection after its final frame.
+            Err(error) if error.kind() != std::io::ErrorKind::NotConnected => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socket")
+            .field("peer", &self.peer)
+            .field("read_timeout", &self.read_timeout)
+            .field("bytes_read", &self.bytes_read)
+            .field("bytes_written", &self.bytes_written)
+            .finish_non_exhaustive()
     }
 }
@@ -17,7 +17,7 @@ use crate::exceptions::Exception;
 /// Create an ECC key
 ///
 /// `generate_key_pair` will create an ECC key and return a (private key, public key) pair of `(EphemeralSecret, PublicKey)`.
-/// 
+///
 /// We use `X25519` curve for ECC operations.
 ///
 /// ```rust
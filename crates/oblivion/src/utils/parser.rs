@@ -3,6 +3,7 @@
 //! Used to parse and reconstruct data and store it.
 use anyhow::{Error, Result};
 use regex::Regex;
+use serde_json::Value;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 
@@ -36,6 +37,8 @@ pub fn length(bytes: &[u8]) -> Result<[u8; 4], Exception> {
     Ok(size.to_be_bytes())
 }
 
+const UNIX_SCHEME: &str = "oblivion+unix://";
+
 /// Oblivion Location Path String Parser
 ///
 /// ```rust
@@ -48,6 +51,22 @@ pub fn length(bytes: &[u8]) -> Result<[u8; 4], Exception> {
 /// assert_eq!("813".to_string(), entrance.get_port());
 /// assert_eq!("/test".to_string(), entrance.get_entrance());
 /// ```
+///
+/// `oblivion+ws` locations reach a server accepting WebSocket upgrades, see
+/// `ServerBuilder::websocket`.
+///
+/// Unix domain sockets are addressed with the `oblivion+unix` scheme, the socket path
+/// ends at the first segment ending with `.sock` and the rest is the entrance:
+///
+/// ```rust
+/// use oblivion::utils::parser::OblivionPath;
+///
+/// let entrance = OblivionPath::new("oblivion+unix:///run/app.sock/entrance").unwrap();
+///
+/// assert_eq!("oblivion+unix", entrance.get_protocol());
+/// assert_eq!(Some("/run/app.sock"), entrance.get_socket_path());
+/// assert_eq!("/entrance", entrance.get_entrance());
+/// ```
 pub struct OblivionPath {
     protocol: String,
     host: String,
@@ -57,8 +76,12 @@ pub struct OblivionPath {
 
 impl OblivionPath {
     pub fn new(path: &str) -> Result<Self> {
+        if let Some(socket) = path.strip_prefix(UNIX_SCHEME) {
+            return Self::new_unix(path, socket);
+        }
+
         let re = Regex::new(
-            r"^(?P<protocol>oblivion)?(?:://)?(?P<host>[^:/]+)(:(?P<port>\d+))?(?P<entrance>.+)?$",
+            r"^(?P<protocol>oblivion(?:\+ws)?)?(?:://)?(?P<host>[^:/]+)(:(?P<port>\d+))?(?P<entrance>.+)?$",
         )?;
 
         if let Some(captures) = re.captures(path) {
@@ -98,6 +121,51 @@ impl OblivionPath {
         }
     }
 
+    fn new_unix(path: &str, socket: &str) -> Result<Self> {
+        if !socket.starts_with('/') {
+            return Err(Error::from(Exception::InvalidOblivion {
+                entrance: path.to_string(),
+            }));
+        }
+        let split = socket
+            .match_indices('/')
+            .map(|(index, _)| index)
+            .chain([socket.len()])
+            .find(|index| *index > 0 && socket[..*index].ends_with(".sock"))
+            .unwrap_or(socket.len());
+        let (host, entrance) = socket.split_at(split);
+        Ok(Self {
+            protocol: "oblivion+unix".to_string(),
+            host: host.to_string(),
+            port: String::new(),
+            entrance: if entrance.is_empty() {
+                "/".to_string()
+            } else {
+                entrance.to_string()
+            },
+        })
+    }
+
+    /// Location of `entrance` on a pre-connected stream, which has no host.
+    pub(crate) fn local(entrance: &str) -> Self {
+        Self {
+            protocol: "oblivion".to_string(),
+            host: String::new(),
+            port: String::new(),
+            entrance: entrance.to_string(),
+        }
+    }
+
+    /// Path of the Unix domain socket for `oblivion+unix` locations.
+    pub fn get_socket_path(&self) -> Option<&str> {
+        self.is_unix().then_some(self.host.as_str())
+    }
+
+    #[inline]
+    pub fn is_unix(&self) -> bool {
+        self.protocol == "oblivion+unix"
+    }
+
     pub fn get_protocol(&self) -> &str {
         &self.protocol
     }
@@ -116,15 +184,17 @@ impl OblivionPath {
 }
 
 /// Oblivion Request Header Parser
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct OblivionRequest {
     pub(crate) method: String,
     pub(crate) entrance: String,
-    protocol: String,
+    pub(crate) protocol: String,
     version: String,
     remote_addr: String,
     remote_port: u16,
     pub(crate) aes_key: Option<[u8; 16]>,
+    pub(crate) headers: HashMap<String, String>,
+    pub(crate) body: Vec<u8>,
 }
 
 impl OblivionRequest {
@@ -159,6 +229,8 @@ impl OblivionRequest {
             remote_addr: String::new(),
             remote_port: 0,
             aes_key: None,
+            headers: HashMap::new(),
+            body: Vec::new(),
         })
     }
 
@@ -183,6 +255,46 @@ impl OblivionRequest {
         &self.version
     }
 
+    /// Whether the client speaks version `major.minor` of the protocol or a later one.
+    ///
+    /// ```rust
+    /// use oblivion::utils::parser::OblivionRequest;
+    ///
+    /// let request = OblivionRequest::new("CONNECT /welcome Oblivion/2.1").unwrap();
+    /// assert!(request.supports_version(2, 0));
+    /// assert!(request.supports_version(2, 1));
+    /// assert!(!request.supports_version(2, 2));
+    /// ```
+    pub fn supports_version(&self, major: u32, minor: u32) -> bool {
+        let mut parts = self
+            .version
+            .split('.')
+            .map(|part| part.parse::<u32>().unwrap_or(0));
+        (parts.next().unwrap_or(0), parts.next().unwrap_or(0)) >= (major, minor)
+    }
+
+    /// Value of the header `name` sent along with the request, which is case insensitive.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Headers sent along with the request by lowercase name.
+    pub fn get_headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Body sent along with the request, empty unless the client sent one.
+    pub fn get_body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Body sent along with the request parsed as JSON.
+    pub fn get_json(&self) -> Result<Value> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
     pub fn get_ip(&self) -> &str {
         &self.remote_addr
     }
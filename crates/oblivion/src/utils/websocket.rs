@@ -0,0 +1,206 @@
+//! # Oblivion WebSocket Transport
+//!
+//! Carries the Oblivion handshake and OED frames as binary WebSocket messages,
+//! so Oblivion can pass through proxies that only speak HTTP.
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use anyhow::Result;
+use futures::{Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::{Bytes, Error as WsError, Message};
+use tokio_tungstenite::WebSocketStream;
+
+use super::gear::{OwnedReadHalf, OwnedWriteHalf, Socket};
+
+/// Path the WebSocket listener accepts upgrades on unless configured otherwise.
+pub const DEFAULT_WEBSOCKET_PATH: &str = "/oblivion";
+
+/// Byte stream over a WebSocket connection.
+///
+/// Every write is sent as one binary message, incoming binary messages are read
+/// back to back and a close message ends the stream.
+pub struct WebSocketTransport<S> {
+    inner: WebSocketStream<S>,
+    buffer: Bytes,
+}
+
+impl<S> WebSocketTransport<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            buffer: Bytes::new(),
+        }
+    }
+}
+
+fn to_io(error: WsError) -> io::Error {
+    match error {
+        WsError::Io(error) => error,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => {
+            io::Error::from(io::ErrorKind::NotConnected)
+        }
+        error => io::Error::other(error),
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketTransport<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while this.buffer.is_empty() {
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => this.buffer = data,
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                // Pings are answered by the WebSocket stream itself.
+                Some(Ok(_)) => {}
+                Some(Err(error)) => return Poll::Ready(Err(to_io(error))),
+            }
+        }
+        let len = this.buffer.len().min(buf.remaining());
+        buf.put_slice(&this.buffer.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketTransport<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let inner = &mut self.get_mut().inner;
+        ready!(Pin::new(&mut *inner).poll_ready(cx)).map_err(to_io)?;
+        Pin::new(inner)
+            .start_send(Message::binary(buf.to_vec()))
+            .map_err(to_io)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll_flush(cx)
+            .map_err(to_io)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // The peer may already have dropped the connection after its final frame.
+        match ready!(Pin::new(&mut self.get_mut().inner).poll_close(cx)).map_err(to_io) {
+            Err(error)
+                if !matches!(
+                    error.kind(),
+                    io::ErrorKind::NotConnected
+                        | io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                Poll::Ready(Err(error))
+            }
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+}
+
+/// The read and write halves of a [`Socket`] rejoined into a single stream.
+struct Halves {
+    reader: OwnedReadHalf,
+    writer: OwnedWriteHalf,
+}
+
+impl AsyncRead for Halves {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().reader).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Halves {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().writer).poll_shutdown(cx)
+    }
+}
+
+/// Accept a WebSocket upgrade on `path` over an accepted connection.
+///
+/// Upgrades to any other path are answered with `404 Not Found`.
+pub async fn accept(socket: Socket, path: &str) -> Result<Socket> {
+    let peer = socket.peer();
+    let (reader, writer) = socket.into_halves();
+    // The error response type is dictated by the WebSocket handshake callback.
+    #[allow(clippy::result_large_err)]
+    let callback = |request: &Request, response: Response| {
+        if request.uri().path() == path {
+            Ok(response)
+        } else {
+            let mut response = ErrorResponse::new(None);
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            Err(response)
+        }
+    };
+    let stream = tokio_tungstenite::accept_hdr_async(Halves { reader, writer }, callback).await?;
+    Ok(Socket::from_stream(WebSocketTransport::new(stream), peer))
+}
+
+/// Connect to a WebSocket listener at `url`, such as `ws://127.0.0.1:7076/oblivion`.
+///
+/// Clients usually go through `Client::connect` with an `oblivion+ws` location:
+///
+/// ```rust
+/// use oblivion::models::client::Client;
+/// use oblivion::models::router::Router;
+/// use oblivion::models::server::Server;
+/// use tokio::net::TcpListener;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let listener = TcpListener::bind("127.0.0.1:0").await?;
+/// let server = Server::builder(Router::new())
+///     .listener(listener)
+///     .websocket("/oblivion")
+///     .build();
+/// let address = server.local_addr().unwrap();
+/// tokio::spawn(async move { server.run().await });
+///
+/// let client = Client::connect(&format!("oblivion+ws://{}/welcome", address)).await?;
+/// assert!(client.recv().await?.text()?.contains("is not found"));
+/// # Ok(())
+/// # }
+/// ```
+pub async fn connect(url: &str) -> Result<Socket> {
+    let (stream, _) = tokio_tungstenite::connect_async(url).await?;
+    let peer = match stream.get_ref() {
+        tokio_tungstenite::MaybeTlsStream::Plain(tcp) => tcp.peer_addr().ok(),
+        _ => None,
+    };
+    Ok(Socket::from_stream(WebSocketTransport::new(stream), peer))
+}
+
+/// Run the WebSocket client handshake for `url` over an already connected TCP stream,
+/// e.g. one tunneled through a proxy.
+pub async fn client(url: &str, tcp: TcpStream) -> Result<Socket> {
+    let peer = tcp.peer_addr().ok();
+    let (stream, _) = tokio_tungstenite::client_async(url, tcp).await?;
+    Ok(Socket::from_stream(WebSocketTransport::new(stream), peer))
+}